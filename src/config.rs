@@ -1,7 +1,340 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use serde::Deserialize;
 
+use crate::link::LinkStyle;
+
 #[derive(Deserialize)]
 pub struct Config {
     /// the time in minutes the bot will wait before fetching again
-    pub bot_fetch_schedule: u32
-}
\ No newline at end of file
+    pub bot_fetch_schedule: u32,
+    /// the amount of latest videos fetched per playlist when checking for new uploads.
+    /// If a channel uploads more videos than this between two checks, only the latest one is reported
+    #[serde(default = "default_fetch_depth")]
+    pub fetch_depth: usize,
+    /// the maximum amount of playlists fetched at the same time
+    #[serde(default = "default_max_parallel_fetches")]
+    pub max_parallel_fetches: usize,
+    /// after how many failed fetches in a row a playlist gets disabled. 0 means never
+    #[serde(default = "default_max_consecutive_failures")]
+    pub max_consecutive_failures: u32,
+    /// the time in seconds yt-dlp might take to fetch a playlist or look up a channel before it gets killed
+    #[serde(default = "default_yt_dlp_timeout")]
+    pub yt_dlp_timeout: u64,
+    /// where the latest videos of the playlists are fetched from
+    #[serde(default)]
+    pub video_source: VideoSourceConfig,
+    /// how failed fetches are retried and how many requests are sent to YouTube
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    /// what to do with new YouTube shorts
+    #[serde(default)]
+    pub shorts: ShortsFilter,
+    /// what to do with new YouTube shorts of specific playlists, by playlist id. Overrides 'shorts'
+    #[serde(default)]
+    pub playlist_shorts: HashMap<String, ShortsFilter>,
+    /// where new videos and errors are sent to, in addition to the telegram bot
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    /// commands which are run for every new video
+    #[serde(default)]
+    pub hooks: Vec<HookConfig>,
+    /// how new videos are downloaded. Nothing is downloaded if not set
+    #[serde(default)]
+    pub download: Option<DownloadConfig>,
+    /// the podcast feeds written after every fetch. No feeds are written if not set
+    #[serde(default)]
+    pub feeds: Option<FeedConfig>,
+    /// the settings of the dashboard and json api started with 'serve'
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// where the links to videos point to. With multiple styles, every notification contains one link per style.
+    /// Users of the bot can choose their own styles
+    #[serde(default = "default_links")]
+    pub links: Vec<LinkStyle>,
+}
+
+/// Where the latest videos of the playlists are fetched from
+#[derive(Clone, Debug, Default, Deserialize)]
+pub enum VideoSourceConfig {
+    /// Run a yt-dlp process for every playlist
+    #[default]
+    YtDlp,
+    /// Use the api of an Invidious instance, like "https://yewtu.be". yt-dlp is used if the instance fails
+    Invidious(String),
+    /// Use the api of a Piped instance, like "https://pipedapi.kavin.rocks". yt-dlp is used if the instance fails
+    Piped(String),
+    /// Poll the Atom feeds YouTube provides for every playlist and only run yt-dlp if a feed changed
+    YouTubeFeed,
+}
+
+/// How failed fetches are retried and how many requests are sent to YouTube, so it does not block new_tube
+#[derive(Clone, Debug, Deserialize)]
+pub struct ThrottleConfig {
+    /// how often a fetch is retried after a transient error, like being rate limited. 0 means never
+    #[serde(default = "default_retry_attempts")]
+    pub retry_attempts: u32,
+    /// the time in seconds before the first retry. It doubles with every further retry
+    #[serde(default = "default_retry_base_delay")]
+    pub retry_base_delay: u64,
+    /// up to this fraction of a delay is randomly added to it, so parallel fetches do not retry at the same time
+    #[serde(default = "default_retry_jitter")]
+    pub retry_jitter: f32,
+    /// the maximum amount of requests per minute, shared by every yt-dlp run and every request to an instance or feed. 0 means unlimited
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// after how many rate limited requests in a row all fetches are paused. 0 means never
+    #[serde(default = "default_pause_after_rate_limits")]
+    pub pause_after_rate_limits: u32,
+    /// the time in minutes all fetches are paused
+    #[serde(default = "default_pause_minutes")]
+    pub pause_minutes: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            retry_attempts: default_retry_attempts(),
+            retry_base_delay: default_retry_base_delay(),
+            retry_jitter: default_retry_jitter(),
+            requests_per_minute: 0,
+            pause_after_rate_limits: default_pause_after_rate_limits(),
+            pause_minutes: default_pause_minutes(),
+        }
+    }
+}
+
+/// What to do with new YouTube shorts. They are always saved in the database.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum ShortsFilter {
+    /// Notify about shorts like about any other video
+    #[default]
+    Include,
+    /// Do not notify about shorts
+    Exclude,
+    /// Send the notifications about shorts to the telegram chat with the given id instead
+    SeparateChat(i64),
+}
+
+/// A command which is run for every new video. It gets the video as JSON on stdin.
+#[derive(Clone, Debug, Deserialize)]
+pub struct HookConfig {
+    /// the program to run
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// the time in seconds the hook might run before it gets killed
+    #[serde(default = "default_hook_timeout")]
+    pub timeout: u64,
+    /// if the notifiers and the default telegram chat should be told when the hook fails
+    #[serde(default)]
+    pub notify_on_failure: bool,
+}
+
+/// Where and how new videos are downloaded
+#[derive(Clone, Debug, Deserialize)]
+pub struct DownloadConfig {
+    /// the directory the videos are saved to
+    pub library_dir: PathBuf,
+    /// the yt-dlp output template of the file names, relative to the library directory
+    #[serde(default = "default_file_name_template")]
+    pub file_name_template: String,
+    /// the yt-dlp format selector for videos
+    #[serde(default = "default_video_format")]
+    pub video_format: String,
+    /// the yt-dlp format selector for audio only downloads
+    #[serde(default = "default_audio_format")]
+    pub audio_format: String,
+    /// the maximum amount of videos downloaded at the same time
+    #[serde(default = "default_max_parallel_downloads")]
+    pub max_parallel_downloads: usize,
+    /// the time in seconds a download might take before yt-dlp gets killed
+    #[serde(default = "default_download_timeout")]
+    pub timeout: u64,
+    /// the playlists whose new videos are downloaded, by playlist id
+    #[serde(default)]
+    pub playlists: HashMap<String, DownloadMode>,
+}
+
+/// What is downloaded from a video
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum DownloadMode {
+    Video,
+    Audio,
+}
+
+/// Where the feeds are written to and what they contain
+#[derive(Clone, Debug, Deserialize)]
+pub struct FeedConfig {
+    /// the directory the feeds are written to
+    pub output_dir: PathBuf,
+    /// the url the output directory is served at, used for the links of the feeds to themselves
+    pub base_url: String,
+    /// the url the download library directory is served at. If set, the feeds point to the downloaded files
+    #[serde(default)]
+    pub media_base_url: Option<String>,
+    /// the maximum amount of videos in a feed
+    #[serde(default = "default_max_feed_items")]
+    pub max_items: usize,
+    /// feeds which combine the videos of multiple playlists, by feed name (letters, digits, '-' and '_'). Every playlist gets its own feed anyway
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    /// added as 'token' query parameter to the links to the feeds and the downloaded files.
+    /// Set it to the token of the server, if podcast apps get them from `new_tube serve`
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Where the server listens and how clients authenticate
+#[derive(Clone, Debug, Deserialize)]
+pub struct ServerConfig {
+    /// the address and port the server listens on
+    #[serde(default = "default_server_address")]
+    pub address: String,
+    /// the token clients must send as 'Authorization: Bearer <token>' header or 'token' query parameter.
+    /// Required to start the server
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: default_server_address(),
+            token: None,
+        }
+    }
+}
+
+/// A target new videos and errors can be sent to.
+#[derive(Clone, Debug, Deserialize)]
+pub enum NotifierConfig {
+    /// Send messages to a telegram chat, using the bot from NEW_TUBE_TELEGRAM_API_KEY.
+    /// The api url replaces the official telegram server
+    Telegram {
+        chat_id: i64,
+        #[serde(default)]
+        api_url: Option<String>,
+    },
+    /// POST every video and error as JSON to the url
+    Webhook {
+        url: String,
+    },
+    /// Publish messages to a topic of a ntfy server, like https://ntfy.sh
+    Ntfy {
+        server: String,
+        topic: String,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// Send messages to a discord channel using a webhook url
+    Discord {
+        webhook_url: String,
+    },
+    /// Send messages to a matrix room
+    Matrix {
+        homeserver: String,
+        access_token: String,
+        room_id: String,
+    },
+    /// Send emails using a SMTP server
+    Email {
+        host: String,
+        /// the default port of the encryption is used if not set
+        #[serde(default)]
+        port: Option<u16>,
+        #[serde(default)]
+        encryption: SmtpEncryption,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+/// How the connection to a SMTP server is secured
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum SmtpEncryption {
+    /// No encryption at all. Only use this for servers in your local network
+    Plain,
+    /// Upgrade the connection with STARTTLS
+    #[default]
+    StartTls,
+    /// Use TLS from the start
+    Tls,
+}
+
+fn default_fetch_depth() -> usize {
+    10
+}
+
+fn default_max_parallel_fetches() -> usize {
+    4
+}
+
+fn default_max_consecutive_failures() -> u32 {
+    10
+}
+
+fn default_yt_dlp_timeout() -> u64 {
+    120
+}
+
+fn default_hook_timeout() -> u64 {
+    30
+}
+
+fn default_file_name_template() -> String {
+    "%(channel)s/%(upload_date)s %(title)s [%(id)s].%(ext)s".to_string()
+}
+
+fn default_video_format() -> String {
+    "bestvideo*+bestaudio/best".to_string()
+}
+
+fn default_audio_format() -> String {
+    "bestaudio/best".to_string()
+}
+
+fn default_max_parallel_downloads() -> usize {
+    2
+}
+
+fn default_download_timeout() -> u64 {
+    3600
+}
+
+fn default_max_feed_items() -> usize {
+    50
+}
+
+fn default_server_address() -> String {
+    "127.0.0.1:8080".to_string()
+}
+
+fn default_links() -> Vec<LinkStyle> {
+    vec![LinkStyle::YouTube]
+}
+
+fn default_retry_attempts() -> u32 {
+    2
+}
+
+fn default_retry_base_delay() -> u64 {
+    10
+}
+
+fn default_retry_jitter() -> f32 {
+    0.5
+}
+
+fn default_pause_after_rate_limits() -> u32 {
+    3
+}
+
+fn default_pause_minutes() -> u64 {
+    30
+}
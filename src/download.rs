@@ -0,0 +1,72 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::ToSql;
+
+/// The download of a single video
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub video_id: String,
+    pub playlist_id: String,
+    /// The title of the video, if it is part of the history
+    pub title: Option<String>,
+    pub status: DownloadStatus,
+    /// Where the video was saved, once the download is done.
+    /// Not set if yt-dlp skipped the video, as it was already in the download archive
+    pub file_path: Option<String>,
+    /// Why the download failed
+    pub error: Option<String>,
+}
+
+/// How far the download of a video is
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DownloadStatus {
+    /// The video waits for a free download slot
+    Queued,
+    Downloading,
+    Done,
+    Failed,
+}
+
+impl DownloadStatus {
+    pub const ALL: [DownloadStatus; 4] = [DownloadStatus::Queued, DownloadStatus::Downloading, DownloadStatus::Done, DownloadStatus::Failed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DownloadStatus::Queued => "queued",
+            DownloadStatus::Downloading => "downloading",
+            DownloadStatus::Done => "done",
+            DownloadStatus::Failed => "failed",
+        }
+    }
+}
+
+impl Display for DownloadStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for DownloadStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DownloadStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("unknown download status '{s}'"))
+    }
+}
+
+impl ToSql for DownloadStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for DownloadStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
@@ -0,0 +1,422 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use error_generator::error;
+
+use crate::config::{DownloadConfig, FeedConfig};
+use crate::link::LinkStyle;
+use crate::new_tube_service::{NewTubeService, NewTubeServiceError};
+use crate::playlist_item::{civil_from_days, PlaylistItem};
+
+type Result<T> = std::result::Result<T, FeedError>;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Numbers the temporary files, so feeds written at the same time never share one
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A video from the history which is part of a feed
+pub struct FeedItem {
+    pub video: PlaylistItem,
+    /// When the video was first seen, as a unix timestamp. Used as its publication date
+    pub first_seen: i64,
+    /// Where the video was downloaded to, if it was
+    pub file_path: Option<String>,
+}
+
+/// A feed to write, with all its items
+struct Feed {
+    /// The name of the feed files, without the extension
+    name: String,
+    title: String,
+    /// The website the feed belongs to
+    link: String,
+    items: Vec<FeedItem>,
+}
+
+/// A downloaded file which is attached to a feed item
+struct Enclosure {
+    url: String,
+    length: u64,
+    mime_type: &'static str,
+}
+
+/// Write a RSS 2.0 podcast feed (<name>.xml) and an Atom feed (<name>.atom) for every playlist and every group
+/// from the config to the output directory. The feeds contain the latest videos from the history,
+/// livestreams which did not end yet are left out.
+///
+/// The items link to the video in the first link style from the config.
+/// If the videos are downloaded and the library is served at an url, the feed items point to the downloaded files.
+/// The feeds are replaced atomically, so a web server never serves half written files.
+/// Nothing is written if the name of a group could not be used as a file name.
+pub fn write_feeds(config: &FeedConfig, download_config: Option<&DownloadConfig>, service: &NewTubeService) -> Result<usize> {
+    if let Some(name) = config.groups.keys().find(|name| !is_valid_group_name(name)) {
+        return Err(FeedError::InvalidGroupName(name.clone()));
+    }
+
+    fs::create_dir_all(&config.output_dir)?;
+    let links = service.get_link_styles(None);
+    let mut feeds = vec![];
+
+    for playlist_id in service.get_playlist_ids()? {
+        let items = service.get_feed_items(slice::from_ref(&playlist_id), config.max_items)?;
+        let title = items.first().map(|item| item.video.uploader.clone()).unwrap_or_else(|| playlist_id.clone());
+
+        feeds.push(Feed {
+            link: links.first().unwrap_or(&LinkStyle::YouTube).playlist_link(&playlist_id),
+            name: playlist_id,
+            title,
+            items,
+        })
+    }
+
+    for (name, playlist_ids) in &config.groups {
+        feeds.push(Feed {
+            name: name.clone(),
+            title: name.clone(),
+            // a group has no website, so its feed is the best place to link to
+            link: feed_url(config, name, "xml"),
+            items: service.get_feed_items(playlist_ids, config.max_items)?,
+        })
+    }
+
+    for feed in &feeds {
+        write_atomically(&config.output_dir.join(format!("{}.xml", feed.name)), &to_rss(config, download_config, links, feed))?;
+        write_atomically(&config.output_dir.join(format!("{}.atom", feed.name)), &to_atom(config, download_config, links, feed))?;
+    }
+
+    Ok(feeds.len())
+}
+
+/// The group names become file names, so they must not contain anything but letters, digits, '-' and '_'
+fn is_valid_group_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_')
+}
+
+/// Write the file next to its destination first, so readers never see a half written feed.
+/// The temporary file is hidden and unique to the process and the write.
+fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let number = TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed);
+    let temporary_path = path.with_file_name(format!(".{file_name}.{}.{number}.tmp", process::id()));
+    fs::write(&temporary_path, content)?;
+    fs::rename(temporary_path, path)?;
+    Ok(())
+}
+
+fn to_rss(config: &FeedConfig, download_config: Option<&DownloadConfig>, links: &[LinkStyle], feed: &Feed) -> String {
+    let items = feed.items
+        .iter()
+        .map(|item| {
+            let enclosure = enclosure(config, download_config, item)
+                .map(|enclosure| format!(
+                    "\n      <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>",
+                    escape(&enclosure.url),
+                    enclosure.length,
+                    enclosure.mime_type
+                ))
+                .unwrap_or_default();
+
+            format!(
+                "    <item>
+      <title>{}</title>
+      <link>{}</link>
+      <guid isPermaLink=\"false\">{}</guid>
+      <pubDate>{}</pubDate>
+      <description>{}</description>
+      <itunes:author>{}</itunes:author>
+      <itunes:duration>{}</itunes:duration>{enclosure}
+    </item>",
+                escape(&item.video.title),
+                escape(&item.video.link(links)),
+                escape(&item.video.video_id),
+                rfc_2822(item.first_seen),
+                escape(&description(&item.video)),
+                escape(&item.video.uploader),
+                item.video.duration as u64,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" xmlns:atom=\"http://www.w3.org/2005/Atom\">
+  <channel>
+    <title>{}</title>
+    <link>{}</link>
+    <description>{}</description>
+    <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>
+    <itunes:author>{}</itunes:author>
+    <itunes:explicit>false</itunes:explicit>
+{items}
+  </channel>
+</rss>
+",
+        escape(&feed.title),
+        escape(&feed.link),
+        escape(&format!("New videos of {}", feed.title)),
+        escape(&feed_url(config, &feed.name, "xml")),
+        escape(&feed.title),
+    )
+}
+
+fn to_atom(config: &FeedConfig, download_config: Option<&DownloadConfig>, links: &[LinkStyle], feed: &Feed) -> String {
+    let updated = feed.items.iter().map(|item| item.first_seen).max().unwrap_or_default();
+    let entries = feed.items
+        .iter()
+        .map(|item| {
+            let enclosure = enclosure(config, download_config, item)
+                .map(|enclosure| format!(
+                    "\n    <link rel=\"enclosure\" href=\"{}\" length=\"{}\" type=\"{}\"/>",
+                    escape(&enclosure.url),
+                    enclosure.length,
+                    enclosure.mime_type
+                ))
+                .unwrap_or_default();
+
+            format!(
+                "  <entry>
+    <title>{}</title>
+    <id>yt:video:{}</id>
+    <link href=\"{}\"/>{enclosure}
+    <updated>{}</updated>
+    <author><name>{}</name></author>
+    <summary>{}</summary>
+  </entry>",
+                escape(&item.video.title),
+                escape(&item.video.video_id),
+                escape(&item.video.link(links)),
+                rfc_3339(item.first_seen),
+                escape(&item.video.uploader),
+                escape(&description(&item.video)),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<feed xmlns=\"http://www.w3.org/2005/Atom\">
+  <title>{}</title>
+  <id>urn:new_tube:{}</id>
+  <updated>{}</updated>
+  <link href=\"{}\"/>
+  <link rel=\"self\" href=\"{}\"/>
+{entries}
+</feed>
+",
+        escape(&feed.title),
+        escape(&encode_path(&feed.name)),
+        rfc_3339(updated),
+        escape(&feed.link),
+        escape(&feed_url(config, &feed.name, "atom")),
+    )
+}
+
+fn description(video: &PlaylistItem) -> String {
+    format!("{} ({})", video.uploader, video.formatted_duration())
+}
+
+fn feed_url(config: &FeedConfig, name: &str, extension: &str) -> String {
+    with_token(config, format!("{}/{}.{extension}", config.base_url.trim_end_matches('/'), encode_path(name)))
+}
+
+/// Add the token from the config to the url, so the server lets podcast apps in
+fn with_token(config: &FeedConfig, url: String) -> String {
+    match config.token {
+        Some(ref token) => format!("{url}?token={}", encode_path(token).replace('/', "%2F")),
+        None => url
+    }
+}
+
+/// Create the enclosure of a downloaded video. The file must be inside the library directory,
+/// which is served at the media base url.
+fn enclosure(config: &FeedConfig, download_config: Option<&DownloadConfig>, item: &FeedItem) -> Option<Enclosure> {
+    let media_base_url = config.media_base_url.as_ref()?;
+    let file_path = Path::new(item.file_path.as_ref()?);
+    let relative_path = file_path.strip_prefix(&download_config?.library_dir).ok()?;
+
+    Some(Enclosure {
+        url: with_token(config, format!("{}/{}", media_base_url.trim_end_matches('/'), encode_path(&relative_path.to_string_lossy()))),
+        // the file might have been deleted by the user
+        length: fs::metadata(file_path).ok()?.len(),
+        mime_type: mime_type(file_path),
+    })
+}
+
+/// Guess the mime type of a downloaded file from its extension
+pub fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mkv") => "video/x-matroska",
+        Some("m4a") => "audio/mp4",
+        Some("mp3") => "audio/mpeg",
+        Some("opus" | "ogg") => "audio/ogg",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Percent encode everything in the path except unreserved characters and slashes
+fn encode_path(path: &str) -> String {
+    path
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}")
+        })
+        .collect()
+}
+
+/// Escape the text for XML and HTML
+pub fn escape(text: &str) -> String {
+    text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Format the timestamp like 'Sun, 18 Oct 2026 05:28:14 +0000', as required by RSS
+fn rfc_2822(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds_of_day = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {day:02} {} {year} {:02}:{:02}:{:02} +0000",
+        // 1970-01-01 was a thursday
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize],
+        seconds_of_day / 3600,
+        (seconds_of_day / 60) % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Format the timestamp like '2026-10-18T05:28:14Z', as required by Atom
+fn rfc_3339(timestamp: i64) -> String {
+    let seconds_of_day = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        (seconds_of_day / 60) % 60,
+        seconds_of_day % 60
+    )
+}
+
+#[error]
+pub enum FeedError {
+    #[error(message = "Failed to write feed: {_0}", impl_from)]
+    WriteFailed(io::Error),
+    #[error(message = "{_0}", impl_from)]
+    NewTubeService(NewTubeServiceError),
+    #[error(message = "The feed group name '{_0}' is invalid, only letters, digits, '-' and '_' are allowed")]
+    InvalidGroupName(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::new_tube_service::database::Database;
+    use crate::new_tube_service::fixture_source::FixtureSource;
+    use crate::new_tube_service::throttle::{FakeClock, Throttle};
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/yt_dlp_playlists.jsonl");
+
+    fn service() -> NewTubeService {
+        service_with_config("(bot_fetch_schedule: 5)")
+    }
+
+    fn service_with_config(config: &str) -> NewTubeService {
+        let config = ron::from_str::<Config>(config).unwrap();
+        let throttle = Arc::new(Throttle::new(Default::default(), FakeClock::new()));
+        let service = NewTubeService::with_video_source(&config, Database::open_in_memory().unwrap(), Box::new(FixtureSource::from_yt_dlp_output(FIXTURE)), throttle);
+        service.add_playlist("UUaaaaaaaaaaaaaaaaaaaaaa").unwrap();
+        service
+    }
+
+    fn feed_config(output_dir: PathBuf, group: &str) -> FeedConfig {
+        FeedConfig {
+            output_dir,
+            base_url: "https://example.com/feeds/".to_string(),
+            media_base_url: None,
+            max_items: 10,
+            groups: HashMap::from([(group.to_string(), vec!["UUaaaaaaaaaaaaaaaaaaaaaa".to_string()])]),
+            token: None,
+        }
+    }
+
+    #[test]
+    fn group_feeds_link_to_themselves() {
+        let output_dir = env::temp_dir().join(format!("new_tube_feed_test_{}", std::process::id()));
+        let config = feed_config(output_dir.clone(), "wood-works_2");
+
+        assert_eq!(write_feeds(&config, None, &service()).unwrap(), 2);
+
+        let rss = fs::read_to_string(output_dir.join("wood-works_2.xml")).unwrap();
+        assert!(rss.contains("<link>https://example.com/feeds/wood-works_2.xml</link>"));
+        let atom = fs::read_to_string(output_dir.join("wood-works_2.atom")).unwrap();
+        assert!(atom.contains("<link href=\"https://example.com/feeds/wood-works_2.xml\"/>"));
+        // no temporary files are left
+        assert_eq!(fs::read_dir(&output_dir).unwrap().count(), 4);
+
+        fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn playlist_feeds_link_to_the_playlist_in_the_first_link_style() {
+        let output_dir = env::temp_dir().join(format!("new_tube_link_feed_test_{}", std::process::id()));
+        let service = service_with_config("(bot_fetch_schedule: 5, links: [Invidious(\"https://yewtu.be\"), YouTube])");
+
+        write_feeds(&feed_config(output_dir.clone(), "wood-works"), None, &service).unwrap();
+
+        let rss = fs::read_to_string(output_dir.join("UUaaaaaaaaaaaaaaaaaaaaaa.xml")).unwrap();
+        assert!(rss.contains("<link>https://yewtu.be/playlist?list=UUaaaaaaaaaaaaaaaaaaaaaa</link>"));
+
+        fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn links_contain_the_token() {
+        let output_dir = env::temp_dir().join(format!("new_tube_token_feed_test_{}", std::process::id()));
+        let config = FeedConfig {
+            token: Some("a/b c".to_string()),
+            ..feed_config(output_dir.clone(), "wood-works")
+        };
+
+        write_feeds(&config, None, &service()).unwrap();
+
+        let rss = fs::read_to_string(output_dir.join("wood-works.xml")).unwrap();
+        assert!(rss.contains("<link>https://example.com/feeds/wood-works.xml?token=a%2Fb%20c</link>"));
+
+        fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn group_names_which_are_no_file_names_are_rejected() {
+        let output_dir = env::temp_dir().join(format!("new_tube_invalid_feed_test_{}", std::process::id()));
+
+        for name in ["../x", "a/b", "", ".hidden", "wood works"] {
+            let result = write_feeds(&feed_config(output_dir.clone(), name), None, &service());
+            assert!(matches!(result, Err(FeedError::InvalidGroupName(_))), "{name}");
+        }
+
+        assert!(!output_dir.exists());
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt::{Display, Formatter};
+
+use regex::Regex;
+
+use crate::playlist_item::PlaylistItem;
+
+/// A rule a new video of a playlist must pass before the user gets notified about it
+#[derive(Clone, Debug)]
+pub struct Filter {
+    /// ID of the filter, used to remove it
+    pub id: i64,
+    /// ID of the playlist the filter belongs to
+    pub playlist_id: String,
+    /// The user of the bot who added the filter and whose notifications it filters.
+    /// Filters without a user (like the ones added with the CLI) apply to everyone
+    pub user_id: Option<i64>,
+    pub rule: FilterRule,
+}
+
+/// The different rules a video can be filtered by. Regexes are compiled once, when the rule is created
+#[derive(Clone, Debug)]
+pub enum FilterRule {
+    /// The title must match this regex. If a playlist has multiple of these, one of them must match
+    TitleInclude(Regex),
+    /// The title must not match this regex
+    TitleExclude(Regex),
+    /// The video must be at least this many seconds long
+    MinDuration(u32),
+    /// The video must be at most this many seconds long
+    MaxDuration(u32),
+    /// The video must be uploaded by this channel. If a playlist has multiple of these, the
+    /// video must be uploaded by one of them. Useful for playlists containing collaborations
+    Uploader(String),
+}
+
+impl FilterRule {
+    pub const KINDS: [&'static str; 5] = ["title-include", "title-exclude", "min-duration", "max-duration", "uploader"];
+
+    /// Create a rule from its kind (one of KINDS) and value. Regexes and durations are validated.
+    pub fn parse(kind: &str, value: &str) -> Result<Self, String> {
+        let parse_regex = |value: &str| Regex::new(value).map_err(|e| format!("invalid regex '{value}': {e}"));
+        let parse_seconds = |value: &str| value
+            .parse::<u32>()
+            .map_err(|_| format!("invalid duration '{value}', expected the amount of seconds"));
+
+        match kind {
+            "title-include" => parse_regex(value).map(FilterRule::TitleInclude),
+            "title-exclude" => parse_regex(value).map(FilterRule::TitleExclude),
+            "min-duration" => parse_seconds(value).map(FilterRule::MinDuration),
+            "max-duration" => parse_seconds(value).map(FilterRule::MaxDuration),
+            "uploader" => Ok(FilterRule::Uploader(value.to_string())),
+            _ => Err(format!("unknown filter rule '{kind}', expected one of {}", Self::KINDS.join(", ")))
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FilterRule::TitleInclude(_) => "title-include",
+            FilterRule::TitleExclude(_) => "title-exclude",
+            FilterRule::MinDuration(_) => "min-duration",
+            FilterRule::MaxDuration(_) => "max-duration",
+            FilterRule::Uploader(_) => "uploader",
+        }
+    }
+
+    pub fn value(&self) -> String {
+        match self {
+            FilterRule::TitleInclude(regex) | FilterRule::TitleExclude(regex) => regex.as_str().to_string(),
+            FilterRule::Uploader(value) => value.clone(),
+            FilterRule::MinDuration(seconds) | FilterRule::MaxDuration(seconds) => seconds.to_string(),
+        }
+    }
+}
+
+impl Display for FilterRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind(), self.value())
+    }
+}
+
+/// Check if the video passes all the given rules.
+///
+/// Duration rules are ignored for videos without a known duration, like running livestreams.
+pub fn passes_rules(video: &PlaylistItem, rules: &[FilterRule]) -> bool {
+    let matches = |regex: &Regex| regex.is_match(&video.title);
+    let duration_known = video.duration > 0.0;
+
+    let includes = rules.iter().filter_map(|rule| match rule {
+        FilterRule::TitleInclude(regex) => Some(regex),
+        _ => None
+    }).collect::<Vec<_>>();
+    let uploaders = rules.iter().filter_map(|rule| match rule {
+        FilterRule::Uploader(uploader) => Some(uploader),
+        _ => None
+    }).collect::<Vec<_>>();
+
+    let other_rules_pass = rules.iter().all(|rule| match rule {
+        FilterRule::TitleExclude(regex) => !matches(regex),
+        FilterRule::MinDuration(seconds) => !duration_known || video.duration >= *seconds as f32,
+        FilterRule::MaxDuration(seconds) => !duration_known || video.duration <= *seconds as f32,
+        FilterRule::TitleInclude(_) | FilterRule::Uploader(_) => true
+    });
+
+    other_rules_pass
+        && (includes.is_empty() || includes.into_iter().any(matches))
+        && (uploaders.is_empty() || uploaders.contains(&&video.uploader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playlist_item::VideoKind;
+
+    fn video(title: &str, duration: f32, uploader: &str) -> PlaylistItem {
+        PlaylistItem {
+            playlist_id: "UUaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            video_id: "video".to_string(),
+            title: title.to_string(),
+            duration,
+            uploader: uploader.to_string(),
+            previous_video_id: String::new(),
+            kind: VideoKind::Upload,
+            release_timestamp: None,
+            short: false,
+        }
+    }
+
+    fn rule(kind: &str, value: &str) -> FilterRule {
+        FilterRule::parse(kind, value).unwrap()
+    }
+
+    #[test]
+    fn parse_rejects_invalid_rules() {
+        assert!(FilterRule::parse("title-include", "(unclosed").is_err());
+        assert!(FilterRule::parse("min-duration", "ten").is_err());
+        assert!(FilterRule::parse("unknown", "value").is_err());
+        assert_eq!(rule("title-exclude", "(?i)trailer").value(), "(?i)trailer");
+    }
+
+    #[test]
+    fn passes_rules_checks_every_kind_of_rule() {
+        let rules = [
+            rule("title-include", "(?i)part \\d"),
+            rule("title-include", "Shop tour"),
+            rule("title-exclude", "(?i)trailer"),
+            rule("min-duration", "60"),
+            rule("max-duration", "3600"),
+        ];
+
+        assert!(passes_rules(&video("Workbench, Part 2", 600.0, "Wood Works"), &rules));
+        assert!(passes_rules(&video("Shop tour", 600.0, "Wood Works"), &rules));
+        assert!(!passes_rules(&video("Sharpening chisels", 600.0, "Wood Works"), &rules));
+        assert!(!passes_rules(&video("Part 3 trailer", 600.0, "Wood Works"), &rules));
+        assert!(!passes_rules(&video("Part 3", 30.0, "Wood Works"), &rules));
+        assert!(!passes_rules(&video("Part 3", 7200.0, "Wood Works"), &rules));
+        // the duration of running livestreams is unknown
+        assert!(passes_rules(&video("Part 3", 0.0, "Wood Works"), &rules));
+    }
+
+    #[test]
+    fn passes_rules_allows_one_of_the_uploaders() {
+        let rules = [rule("uploader", "Wood Works"), rule("uploader", "Metal Works")];
+
+        assert!(passes_rules(&video("Collab", 600.0, "Metal Works"), &rules));
+        assert!(!passes_rules(&video("Collab", 600.0, "Someone else"), &rules));
+        assert!(passes_rules(&video("Anything", 600.0, "Someone else"), &[]));
+    }
+}
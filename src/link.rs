@@ -0,0 +1,81 @@
+use std::fmt::{Display, Formatter};
+
+use serde::Deserialize;
+
+/// Where the links to videos point to. YouTube itself is only one of the options,
+/// the other front-ends let the user watch videos without being tracked as much.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum LinkStyle {
+    /// https://www.youtube.com/watch?v=<video id>
+    YouTube,
+    /// The embedded player on youtube-nocookie.com, which does not set cookies before the video is played
+    NoCookie,
+    /// An Invidious instance, like "https://yewtu.be"
+    Invidious(String),
+    /// A Piped instance, like "https://piped.video"
+    Piped(String),
+}
+
+impl LinkStyle {
+    pub const KINDS: [&'static str; 4] = ["youtube", "nocookie", "invidious=<url>", "piped=<url>"];
+
+    /// Create a full url to the video with the given id
+    pub fn link(&self, video_id: &str) -> String {
+        match self {
+            LinkStyle::YouTube => format!("https://www.youtube.com/watch?v={video_id}"),
+            LinkStyle::NoCookie => format!("https://www.youtube-nocookie.com/embed/{video_id}"),
+            LinkStyle::Invidious(instance) | LinkStyle::Piped(instance) => format!("{}/watch?v={video_id}", instance.trim_end_matches('/')),
+        }
+    }
+
+    /// Create a full url to the playlist with the given id
+    pub fn playlist_link(&self, playlist_id: &str) -> String {
+        match self {
+            LinkStyle::YouTube => format!("https://www.youtube.com/playlist?list={playlist_id}"),
+            LinkStyle::NoCookie => format!("https://www.youtube-nocookie.com/embed/videoseries?list={playlist_id}"),
+            LinkStyle::Invidious(instance) | LinkStyle::Piped(instance) => format!("{}/playlist?list={playlist_id}", instance.trim_end_matches('/')),
+        }
+    }
+
+    /// Parse a style written like in KINDS, for example 'invidious=https://yewtu.be'
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let parse_instance = |instance: &str| if instance.starts_with("https://") || instance.starts_with("http://") {
+            Ok(instance.trim_end_matches('/').to_string())
+        } else {
+            Err(format!("invalid instance '{instance}', expected an url like https://example.org"))
+        };
+
+        match value.split_once('=') {
+            None if value == "youtube" => Ok(LinkStyle::YouTube),
+            None if value == "nocookie" => Ok(LinkStyle::NoCookie),
+            Some(("invidious", instance)) => parse_instance(instance).map(LinkStyle::Invidious),
+            Some(("piped", instance)) => parse_instance(instance).map(LinkStyle::Piped),
+            _ => Err(format!("unknown link style '{value}', expected one of {}", Self::KINDS.join(", ")))
+        }
+    }
+}
+
+impl Display for LinkStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkStyle::YouTube => write!(f, "youtube"),
+            LinkStyle::NoCookie => write!(f, "nocookie"),
+            LinkStyle::Invidious(instance) => write!(f, "invidious={instance}"),
+            LinkStyle::Piped(instance) => write!(f, "piped={instance}"),
+        }
+    }
+}
+
+/// Parse multiple styles, separated by whitespace
+pub fn parse_link_styles(value: &str) -> Result<Vec<LinkStyle>, String> {
+    value.split_whitespace().map(LinkStyle::parse).collect()
+}
+
+/// Write multiple styles separated by spaces, so they can be read again with parse_link_styles
+pub fn format_link_styles(styles: &[LinkStyle]) -> String {
+    styles
+        .iter()
+        .map(LinkStyle::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,18 +1,28 @@
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
-use std::{env, io};
+use std::{env, io, process};
 
-use clap::Parser;
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, Parser, Subcommand};
 use cli_table::table::{Table, Width};
 use error_generator::error;
 use ron::error::SpannedError;
 
 use crate::config::Config;
+use crate::download::{Download, DownloadStatus};
 use crate::dump::{dump_playlist_ids, load_playlists_dump, DumpError};
+use crate::feed::{write_feeds, FeedError};
+use crate::filter::{Filter, FilterRule};
+use crate::link::LinkStyle;
 use crate::new_tube_service::database::Database;
-use crate::new_tube_service::NewTubeService;
-use crate::playlist_item::PlaylistItem;
+use crate::new_tube_service::downloader::Downloader;
+use crate::new_tube_service::process::kill_running_processes;
+use crate::new_tube_service::{FetchResult, NewTubeService};
+use crate::notifier::{create_notifiers, notify_all, NotifierError};
+use crate::playlist_item::{PlaylistItem, VideoKind};
+use crate::playlist_status::PlaylistStatus;
+use crate::server::{Server, ServerError};
 use crate::telegram_bot::Bot;
 
 mod environment;
@@ -21,23 +31,55 @@ mod telegram_bot;
 mod config;
 mod playlist_item;
 mod dump;
+mod user;
+mod playlist_status;
+mod filter;
+mod notifier;
+mod download;
+mod feed;
+mod outputs;
+mod server;
+mod link;
+mod shutdown;
+#[cfg(test)]
+mod mock_server;
 
 type Result<T> = std::result::Result<T, NewTubeError>;
 
 fn main() -> Result<()> {
     let config = load_config()?;
+    kill_processes_on_shutdown();
 
-    match Command::parse() {
-        Command::Add(add_command) => add(&add_command.playlist_id),
-        Command::AddAll(add_all_command) => add_all(add_all_command.playlists_json_path),
-        Command::New => new(),
-        Command::Last => last(),
+    let result = match Command::parse() {
+        Command::Add(add_command) => add(&config, &add_command.playlist_id),
+        Command::AddAll(add_all_command) => add_all(&config, add_all_command.playlists_json_path),
+        Command::New => new(&config),
+        Command::Last => last(&config),
+        Command::History(history_command) => history(&config, history_command),
+        Command::Migrate(migrate_command) => migrate(migrate_command),
+        Command::Status => status(&config),
+        Command::Notify(notify_command) => notify(&config, notify_command),
+        Command::Filter(filter_command) => filter(&config, filter_command.action),
+        Command::Downloads(downloads_command) => downloads(downloads_command),
+        Command::Enable(enable_command) => set_enabled(&config, &enable_command.playlist_id, true),
+        Command::Disable(disable_command) => set_enabled(&config, &disable_command.playlist_id, false),
         Command::DumpPlaylistIds => Ok(dump_playlist_ids()?),
-        Command::LoadPlaylistIdsDump => Ok(load_playlist_ids_dump()?),
+        Command::LoadPlaylistIdsDump => Ok(load_playlist_ids_dump(&config)?),
         Command::Bot => Ok(Bot::run(config)?),
-        Command::Replace(replace_command) => replace(&replace_command.old_playlist_id, &replace_command.new_playlist_id),
-        Command::Delete(delete_command) => delete(&delete_command.playlist_id),
+        Command::Serve => Ok(Server::run(config)?),
+        Command::Replace(replace_command) => replace(&config, &replace_command.old_playlist_id, &replace_command.new_playlist_id),
+        Command::Delete(delete_command) => delete(&config, &delete_command.playlist_id),
+    };
+
+    // a command which stopped on its own exits like it was stopped by the signal
+    if shutdown::requested() {
+        if let Err(err) = result {
+            println!("Error: {err}")
+        }
+        process::exit(shutdown::exit_code())
     }
+
+    result
 }
 
 fn load_config() -> Result<Config> {
@@ -50,74 +92,250 @@ fn load_config() -> Result<Config> {
     Ok(config)
 }
 
-fn add(id: &str) -> Result<()> {
-    let video_service = NewTubeService::new()?;
-    Ok(video_service.add_playlist(id)?)
+/// yt-dlp and the hooks run in their own process groups, so they do not get the signals which stop new_tube.
+/// They are killed before new_tube exits instead, so no process is left behind.
+/// Commands which stop gracefully, like the bot, are only told to stop and exit on their own.
+fn kill_processes_on_shutdown() {
+    let result = ctrlc::set_handler(|| {
+        // requested before the processes are killed, so their failures are known to be caused by the shutdown
+        let graceful = shutdown::request();
+        kill_running_processes();
+
+        if !graceful {
+            process::exit(shutdown::exit_code())
+        }
+    });
+
+    match result {
+        #[cfg(unix)]
+        Ok(()) => shutdown::record_signals(),
+        #[cfg(not(unix))]
+        Ok(()) => {}
+        Err(err) => println!("Failed to set the shutdown handler, running yt-dlp processes might not be killed on shutdown: {err}")
+    }
 }
 
-fn add_all(playlists_json_path: PathBuf) -> Result<()> {
+fn add(config: &Config, id: &str) -> Result<()> {
+    let video_service = NewTubeService::new(config)?;
+    let playlist_id = video_service.add_playlist(id)?;
+    println!("Added playlist {playlist_id}");
+    Ok(())
+}
+
+fn add_all(config: &Config, playlists_json_path: PathBuf) -> Result<()> {
     let json_file = File::open(playlists_json_path).expect("open file");
     let ids: Vec<String> = serde_json::from_reader(json_file).expect("read file");
 
     for id in ids {
-        add(&id)?
+        add(config, &id)?
     }
 
     Ok(())
 }
 
-fn replace(old_id: &str, new_id: &str) -> Result<()> {
-    let service = NewTubeService::new()?;
+fn replace(config: &Config, old_id: &str, new_id: &str) -> Result<()> {
+    let service = NewTubeService::new(config)?;
     service.replace(old_id, new_id)?;
     Ok(())
 }
 
-fn delete(id: &str) -> Result<()> {
-    let service = NewTubeService::new()?;
+fn delete(config: &Config, id: &str) -> Result<()> {
+    let service = NewTubeService::new(config)?;
     service.delete(id)?;
     Ok(())
 }
 
-fn new() -> Result<()> {
-    let service = NewTubeService::new()?;
-    let new_items = service.get_new_videos_and_update_database()?;
-    print_table(new_items);
+/// Stops gracefully, so the running downloads and hooks are finished when new_tube is told to stop
+fn new(config: &Config) -> Result<()> {
+    shutdown::stop_gracefully();
+    let service = NewTubeService::new(config)?;
+    let notifiers = create_notifiers(&config.notifiers)?;
+    let downloader = config.download.as_ref().map(Downloader::new).transpose()?;
+    let result = service.get_new_videos_and_update_database()?;
+    notify_all(&notifiers, &config.links, &result);
+    // the videos are downloaded in the background while the results are printed
+    let downloads = match downloader {
+        Some(ref downloader) => downloader.enqueue_new_videos(&result)?,
+        None => 0
+    };
+    print_table(result.new_videos, &config.links);
+
+    if !result.separate_chat_videos.is_empty() {
+        println!("Shorts:");
+        print_table(result.separate_chat_videos.into_iter().map(|(_, video)| video).collect(), &config.links);
+    }
+
+    for failure in result.failures {
+        println!("Failed to fetch playlist {}: {}", failure.playlist_id, failure.error);
+    }
+
+    for playlist_id in result.disabled {
+        println!("Playlist {playlist_id} failed too often and was disabled");
+    }
+
+    if !result.skipped.is_empty() {
+        println!("YouTube rate limited too many requests, skipped {} playlists", result.skipped.len());
+    }
+
+    if let Some(downloader) = downloader {
+        println!("Downloading {downloads} videos");
+        downloader.finish();
+    }
+
+    // the hooks run in the background too, their failures are only known once they finished
+    let hook_failures = service.finish_hooks();
+    if !hook_failures.is_empty() {
+        notify_all(&notifiers, &config.links, &FetchResult { hook_failures, ..FetchResult::default() });
+    }
+
+    // written after the downloads, so the feeds contain the new files
+    if let Some(ref feed_config) = config.feeds {
+        let amount = write_feeds(feed_config, config.download.as_ref(), &service)?;
+        println!("Wrote {amount} feeds");
+    }
+
     Ok(())
 }
 
-fn last() -> Result<()> {
+fn last(config: &Config) -> Result<()> {
+    let service = NewTubeService::new(config)?;
+    print_table(service.get_last_items()?, &config.links);
+    Ok(())
+}
+
+fn status(config: &Config) -> Result<()> {
+    let service = NewTubeService::new(config)?;
+    print_status_table(service.get_statuses()?);
+    Ok(())
+}
+
+fn set_enabled(config: &Config, id: &str, enabled: bool) -> Result<()> {
+    let service = NewTubeService::new(config)?;
+    service.set_enabled(id, enabled)?;
+    Ok(())
+}
+
+fn notify(config: &Config, notify_command: NotifyCommand) -> Result<()> {
+    let service = NewTubeService::new(config)?;
+    service.set_kind_notification(None, &notify_command.playlist_id, notify_command.kind, notify_command.notify)?;
+    Ok(())
+}
+
+fn filter(config: &Config, action: FilterAction) -> Result<()> {
+    let service = NewTubeService::new(config)?;
+
+    match action {
+        FilterAction::Add(add_command) => {
+            let id = service.add_filter(None, &add_command.playlist_id, &add_command.rule, &add_command.value)?;
+            println!("Added filter {id}");
+        }
+        FilterAction::List(list_command) => print_filter_table(service.get_filters(None, list_command.playlist_id.as_deref())?),
+        FilterAction::Remove(remove_command) => service.remove_filter(None, remove_command.filter_id)?,
+    }
+
+    Ok(())
+}
+
+fn downloads(downloads_command: DownloadsCommand) -> Result<()> {
     let database = Database::open()?;
-    let items = database.query_all_items()?;
-    print_table(items);
+    print_download_table(database.query_downloads(downloads_command.status)?);
+    Ok(())
+}
+
+fn history(config: &Config, history_command: HistoryCommand) -> Result<()> {
+    let service = NewTubeService::new(config)?;
+    let items = service.get_history(
+        history_command.channel.as_deref(),
+        history_command.since.as_deref(),
+    )?;
+    print_table(items, &config.links);
+    Ok(())
+}
+
+fn migrate(migrate_command: MigrateCommand) -> Result<()> {
+    if migrate_command.status {
+        let status = Database::migration_status()?;
+        println!("Schema version: {} of {}", status.current_version, status.latest_version());
+
+        for (index, migration) in status.pending.iter().enumerate() {
+            println!("Pending {}: {}", status.current_version + index + 1, migration.description);
+        }
+    } else {
+        // opening the database applies all pending migrations
+        Database::open()?;
+        println!("Database is up to date");
+    }
+
     Ok(())
 }
 
-fn load_playlist_ids_dump() -> Result<()> {
+fn load_playlist_ids_dump(config: &Config) -> Result<()> {
     let playlist_ids = load_playlists_dump()?;
     let len = playlist_ids.len();
-    let video_service = NewTubeService::new()?;
+    let video_service = NewTubeService::new(config)?;
 
     for (index, id) in playlist_ids.into_iter().enumerate() {
         println!("Adding id {} of {}", index + 1, len);
-        video_service.add_playlist(&id)?
+        video_service.add_playlist(&id)?;
     }
 
     Ok(())
 }
 
-fn print_table(items: Vec<PlaylistItem>) {
+fn print_table(items: Vec<PlaylistItem>, links: &[LinkStyle]) {
     Table::new(|item: PlaylistItem| [
         item.uploader.clone(),
         item.playlist_id.clone(),
         item.title.clone(),
-        item.link(),
+        item.links(links).join(" "),
         item.formatted_duration(),
+        item.headline().unwrap_or_default(),
     ])
-        .header(["Channel", "Playlist ID", "Video", "Link", "Duration"])
-        .column_widths([Width::Dynamic, Width::Dynamic, Width::Max(50), Width::Dynamic, Width::Dynamic])
+        .header(["Channel", "Playlist ID", "Video", "Link", "Duration", "Status"])
+        .column_widths([Width::Dynamic, Width::Dynamic, Width::Max(50), Width::Dynamic, Width::Dynamic, Width::Dynamic])
         .print(items);
 }
 
+fn print_status_table(statuses: Vec<PlaylistStatus>) {
+    Table::new(|status: PlaylistStatus| [
+        status.uploader.clone(),
+        status.playlist_id.clone(),
+        if status.enabled { "yes".to_string() } else { "no".to_string() },
+        status.consecutive_failures.to_string(),
+        status.last_success.clone().unwrap_or_else(|| "never".to_string()),
+        status.last_error.clone().unwrap_or_default(),
+    ])
+        .header(["Channel", "Playlist ID", "Enabled", "Failures", "Last success", "Last error"])
+        .column_widths([Width::Dynamic, Width::Dynamic, Width::Dynamic, Width::Dynamic, Width::Dynamic, Width::Max(50)])
+        .print(statuses);
+}
+
+fn print_download_table(downloads: Vec<Download>) {
+    Table::new(|download: Download| [
+        download.title.clone().unwrap_or_default(),
+        download.playlist_id.clone(),
+        download.video_id.clone(),
+        download.status.to_string(),
+        download.file_path.clone().or(download.error.clone()).unwrap_or_default(),
+    ])
+        .header(["Video", "Playlist ID", "Video ID", "Status", "File / Error"])
+        .column_widths([Width::Max(50), Width::Dynamic, Width::Dynamic, Width::Dynamic, Width::Max(80)])
+        .print(downloads);
+}
+
+fn print_filter_table(filters: Vec<Filter>) {
+    Table::new(|filter: Filter| [
+        filter.id.to_string(),
+        filter.playlist_id.clone(),
+        filter.user_id.map(|user_id| user_id.to_string()).unwrap_or_else(|| "everyone".to_string()),
+        filter.rule.kind().to_string(),
+        filter.rule.value(),
+    ])
+        .header(["ID", "Playlist ID", "User", "Rule", "Value"])
+        .column_widths([Width::Dynamic, Width::Dynamic, Width::Dynamic, Width::Dynamic, Width::Max(50)])
+        .print(filters);
+}
+
 #[derive(Parser)]
 enum Command {
     /// Add a playlist id
@@ -132,25 +350,47 @@ enum Command {
     New,
     /// Show the last video of every playlist in the database.
     Last,
+    /// Show every video new_tube has ever seen, newest first
+    History(HistoryCommand),
+    /// Apply all pending database migrations
+    Migrate(MigrateCommand),
+    /// Show how well fetching every playlist worked recently
+    Status,
+    /// Enable a disabled playlist, so it is fetched again
+    Enable(EnableCommand),
+    /// Disable a playlist, so it is no longer fetched
+    Disable(DisableCommand),
+    /// Set if anyone is notified about a kind of video (like livestreams) from a playlist.
+    /// Users of the bot choose the kinds they get with /notify
+    Notify(NotifyCommand),
+    /// Manage the rules new videos of a playlist must pass to be notified about
+    Filter(FilterCommand),
+    /// Show the downloads of new videos
+    Downloads(DownloadsCommand),
     /// Dump the playlist ids to a json file
     DumpPlaylistIds,
     /// Load the playlist ids from a json file
     LoadPlaylistIdsDump,
     /// Run the telegram bot. Requires NEW_TUBE_TELEGRAM_API_KEY to be set to the
-    /// telegram API key. Updates will be sent to the channel defined by NEW_TUBE_DEFAULT_TELEGRAM_CHANNEL.
-    /// Only the user defined in NEW_TUBE_ALLOWED_BOT_USER can use the bot.
-    Bot
+    /// telegram API key. Updates of playlists nobody subscribed to will be sent to the channel defined by
+    /// NEW_TUBE_DEFAULT_TELEGRAM_CHANNEL. The user defined in NEW_TUBE_ALLOWED_BOT_USER is the admin,
+    /// who must approve every other user.
+    Bot,
+    /// Start a web dashboard and JSON api, which also fetches the new videos periodically.
+    /// The address and token are set in the config
+    Serve,
 }
 
 #[derive(Parser)]
 struct AddCommand {
-    /// The id of an "All Videos" playlist
+    /// The id of an "All Videos" playlist. Channel ids, @handles and URLs of
+    /// channels, playlists and videos are accepted too
     playlist_id: String,
 }
 
 #[derive(Parser)]
 struct AddAllCommand {
-    /// Path to a JSON containing a list of playlist ids to add
+    /// Path to a JSON containing a list of playlist ids (or anything else the add command accepts) to add
     playlists_json_path: PathBuf,
 }
 
@@ -158,10 +398,96 @@ struct AddAllCommand {
 struct ReplaceCommand {
     /// The old id to be replaced
     old_playlist_id: String,
-    /// The new id to add to new_tube
+    /// The new id to add to new_tube. Accepts the same input as the add command
     new_playlist_id: String,
 }
 
+#[derive(Parser)]
+struct HistoryCommand {
+    /// Only show videos uploaded by this channel
+    #[arg(long)]
+    channel: Option<String>,
+    /// Only show videos first seen on or after this date (YYYY-MM-DD)
+    #[arg(long)]
+    since: Option<String>,
+}
+
+#[derive(Parser)]
+struct DownloadsCommand {
+    /// Only show downloads with this status (queued, downloading, done, failed)
+    #[arg(long)]
+    status: Option<DownloadStatus>,
+}
+
+#[derive(Parser)]
+struct MigrateCommand {
+    /// Only show the schema version and the pending migrations, without applying them
+    #[arg(long)]
+    status: bool,
+}
+
+#[derive(Parser)]
+struct EnableCommand {
+    /// The id of the playlist to enable
+    playlist_id: String,
+}
+
+#[derive(Parser)]
+struct DisableCommand {
+    /// The id of the playlist to disable
+    playlist_id: String,
+}
+
+#[derive(Parser)]
+struct NotifyCommand {
+    /// The id of the playlist
+    playlist_id: String,
+    /// The kind of video: upload, live, upcoming, premiere or past_live
+    kind: VideoKind,
+    /// on to get notified, off to only save the videos
+    #[arg(value_parser = BoolishValueParser::new(), action = ArgAction::Set)]
+    notify: bool,
+}
+
+#[derive(Parser)]
+struct FilterCommand {
+    #[command(subcommand)]
+    action: FilterAction,
+}
+
+#[derive(Subcommand)]
+enum FilterAction {
+    /// Add a filter rule to a playlist
+    Add(AddFilterCommand),
+    /// Show the filter rules
+    List(ListFiltersCommand),
+    /// Remove a filter rule
+    Remove(RemoveFilterCommand),
+}
+
+#[derive(Parser)]
+struct AddFilterCommand {
+    /// The id of the playlist
+    playlist_id: String,
+    /// The kind of rule
+    #[arg(value_parser = FilterRule::KINDS)]
+    rule: String,
+    /// A regex for title rules, the amount of seconds for duration rules or the channel name for uploader rules
+    value: String,
+}
+
+#[derive(Parser)]
+struct ListFiltersCommand {
+    /// Only show the rules of this playlist
+    playlist_id: Option<String>,
+}
+
+#[derive(Parser)]
+struct RemoveFilterCommand {
+    /// The id of the rule, as shown by the list command
+    filter_id: i64,
+}
+
 #[derive(Parser)]
 struct DeleteCommand {
     /// The playlist id of the playlist id to be deleted
@@ -181,5 +507,11 @@ enum NewTubeError {
     #[error(message = "{_0}", impl_from)]
     BotError(telegram_bot::BotError),
     #[error(message = "{_0}", impl_from)]
-    DumpingError(DumpError)
-}
\ No newline at end of file
+    DumpingError(DumpError),
+    #[error(message = "Failed to create notifier: {_0}", impl_from)]
+    NotifierError(NotifierError),
+    #[error(message = "{_0}", impl_from)]
+    FeedError(FeedError),
+    #[error(message = "{_0}", impl_from)]
+    ServerError(ServerError),
+}
@@ -0,0 +1,107 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tiny_http::{Header, Response, Server};
+
+/// A request the mock server received
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+    pub method: String,
+    /// the path including the query
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ReceivedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The answer of the mock server to a request
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A local http server which stands in for the real services in tests.
+/// Every request is answered by the handler and recorded.
+pub struct MockServer {
+    pub url: String,
+    server: Arc<Server>,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl MockServer {
+    pub fn start(handler: impl Fn(&ReceivedRequest) -> MockResponse + Send + 'static) -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").expect("the mock server should start"));
+        let port = server.server_addr().to_ip().expect("the mock server should listen on an ip address").port();
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let (thread_server, thread_requests) = (server.clone(), requests.clone());
+        thread::spawn(move || {
+            for mut request in thread_server.incoming_requests() {
+                let mut body = String::new();
+                let _ = request.as_reader().read_to_string(&mut body);
+                let received = ReceivedRequest {
+                    method: request.method().to_string(),
+                    url: request.url().to_string(),
+                    headers: request.headers().iter().map(|header| (header.field.to_string(), header.value.to_string())).collect(),
+                    body,
+                };
+
+                let answer = handler(&received);
+                thread_requests.lock().unwrap().push(received);
+
+                let mut response = Response::from_string(answer.body).with_status_code(answer.status);
+                for (name, value) in answer.headers {
+                    response.add_header(Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("the header should be valid"));
+                }
+                let _ = request.respond(response);
+            }
+        });
+
+        MockServer {
+            url: format!("http://127.0.0.1:{port}"),
+            server,
+            requests,
+        }
+    }
+
+    /// A server which answers every request with the same status and body
+    pub fn respond_with(status: u16, body: &str) -> Self {
+        let response = MockResponse::new(status, body);
+        Self::start(move |_| response.clone())
+    }
+
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock()
+    }
+}
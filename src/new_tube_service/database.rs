@@ -1,32 +1,57 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::playlist_item::PlaylistItem;
+use crate::download::{Download, DownloadStatus};
+use crate::feed::FeedItem;
+use crate::filter::{Filter, FilterRule};
+use crate::link::{format_link_styles, parse_link_styles};
+use crate::new_tube_service::migrations;
+use crate::new_tube_service::migrations::MigrationStatus;
+use crate::new_tube_service::youtube_feed::FeedCache;
+use crate::playlist_item::{PlaylistItem, VideoKind};
+use crate::playlist_status::PlaylistStatus;
+use crate::user::User;
 use error_generator::error;
-use rusqlite::Connection;
+use rusqlite::types::Type;
+use rusqlite::{Connection, OptionalExtension, Row};
 
 type Result<T> = std::result::Result<T, DBError>;
 
+/// How long to wait for a lock of the database, which might be held by a download worker
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The user id of settings which apply to everyone, like the ones set with the CLI
+const EVERYONE: i64 = 0;
+
 pub struct Database {
     connection: Connection,
 }
 
 impl Database {
+    /// Open the database and bring its schema to the latest version
     pub fn open() -> Result<Self> {
-        let connection = Connection::open(Self::get_path())?;
+        let mut connection = Connection::open(Self::get_path())?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        migrations::migrate(&mut connection)?;
 
-        connection.execute("\
-        CREATE TABLE IF NOT EXISTS PlaylistItems (
-            playlist_id TEXT PRIMARY KEY,
-            video_id TEXT NOT NULL,
-            title TEXT NOT NULL,
-            duration REAL NOT NULL,
-            uploader TEXT NOT NULL,
-            previous_video_id NULL
-        );", [])?;
+        Ok(Database { connection })
+    }
+
+    /// Open an empty database in memory, for tests
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        let mut connection = Connection::open_in_memory()?;
+        migrations::migrate(&mut connection)?;
 
         Ok(Database { connection })
     }
 
+    /// Get the migration status of the database without applying any migrations
+    pub fn migration_status() -> Result<MigrationStatus> {
+        let connection = Connection::open(Self::get_path())?;
+        Ok(migrations::status(&connection)?)
+    }
+
     fn get_path() -> PathBuf {
         let mut path = std::env::current_exe().unwrap();
         path.pop();
@@ -35,18 +60,32 @@ impl Database {
     }
 
     pub fn query_all_items(&self) -> Result<Vec<PlaylistItem>> {
+        self.query_items(false)
+    }
+
+    /// Query the items of all playlists which were not disabled
+    pub fn query_enabled_items(&self) -> Result<Vec<PlaylistItem>> {
+        self.query_items(true)
+    }
+
+    fn query_items(&self, only_enabled: bool) -> Result<Vec<PlaylistItem>> {
         let mut statement = self.connection.prepare("\
-            SELECT * FROM PlaylistItems;
+            SELECT playlist_id, video_id, title, duration, uploader, previous_video_id, kind, release_timestamp, short FROM PlaylistItems
+            WHERE enabled OR NOT ?1
+            ORDER BY playlist_id;
         ")?;
 
-        let result = statement.query_map([], |row| {
+        let result = statement.query_map([only_enabled], |row| {
             Ok(PlaylistItem {
                 playlist_id: row.get(0)?,
                 video_id: row.get(1)?,
                 title: row.get(2)?,
                 duration: row.get(3)?,
                 uploader: row.get(4)?,
-                previous_video_id: row.get(5)?
+                previous_video_id: row.get(5)?,
+                kind: row.get(6)?,
+                release_timestamp: row.get(7)?,
+                short: row.get(8)?,
             })
         })?;
         Ok(result.map(|r| r.unwrap()).collect())
@@ -58,25 +97,618 @@ impl Database {
 
     pub fn add_item(&self, item: &PlaylistItem) -> Result<()> {
         self.connection.execute("\
-            INSERT OR REPLACE INTO PlaylistItems (playlist_id, video_id, title, duration, uploader, previous_video_id)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+            INSERT INTO PlaylistItems (playlist_id, video_id, title, duration, uploader, previous_video_id, kind, release_timestamp, short)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT (playlist_id) DO UPDATE SET
+                video_id = excluded.video_id,
+                title = excluded.title,
+                duration = excluded.duration,
+                uploader = excluded.uploader,
+                previous_video_id = excluded.previous_video_id,
+                kind = excluded.kind,
+                release_timestamp = excluded.release_timestamp,
+                short = excluded.short;
         ", (
             &item.playlist_id,
             &item.video_id,
             &item.title,
             &format!("{}", item.duration),
             &item.uploader,
-            &item.previous_video_id
+            &item.previous_video_id,
+            item.kind,
+            item.release_timestamp,
+            item.short
+        ))?;
+
+        Ok(())
+    }
+
+    /// Replace the latest item of a known playlist. Unlike add_item, a playlist which was deleted in the meantime
+    /// is not added again. Returns false if the playlist does not exist.
+    pub fn update_item(&self, item: &PlaylistItem) -> Result<bool> {
+        let changed = self.connection.execute("\
+            UPDATE PlaylistItems
+            SET video_id = ?2, title = ?3, duration = ?4, uploader = ?5, previous_video_id = ?6, kind = ?7, release_timestamp = ?8, short = ?9
+            WHERE playlist_id = ?1;
+        ", (
+            &item.playlist_id,
+            &item.video_id,
+            &item.title,
+            &format!("{}", item.duration),
+            &item.uploader,
+            &item.previous_video_id,
+            item.kind,
+            item.release_timestamp,
+            item.short
+        ))?;
+
+        Ok(changed > 0)
+    }
+
+    /// Remember that the playlist was fetched successfully just now. Returns false if the playlist does not exist.
+    pub fn record_fetch_success(&self, playlist_id: &str) -> Result<bool> {
+        let changed = self.connection.execute("\
+            UPDATE PlaylistItems
+            SET last_success = strftime('%s', 'now'), last_error = NULL, consecutive_failures = 0
+            WHERE playlist_id = ?1;
+        ", [playlist_id])?;
+        Ok(changed > 0)
+    }
+
+    /// Remember that fetching the playlist failed. Returns how many fetches in a row failed now,
+    /// or None if the playlist does not exist.
+    pub fn record_fetch_failure(&self, playlist_id: &str, error: &str) -> Result<Option<u32>> {
+        Ok(self.connection.query_row("\
+            UPDATE PlaylistItems
+            SET last_error = ?2, consecutive_failures = consecutive_failures + 1
+            WHERE playlist_id = ?1
+            RETURNING consecutive_failures;
+        ", [playlist_id, error], |row| row.get(0)).optional()?)
+    }
+
+    /// Set if the playlist was added by subscribing to it with the bot, or by the admin otherwise
+    pub fn set_added_by_bot(&self, playlist_id: &str, added_by_bot: bool) -> Result<()> {
+        self.connection.execute("\
+            UPDATE PlaylistItems SET added_by_bot = ?2 WHERE playlist_id = ?1;
+        ", (playlist_id, added_by_bot))?;
+        Ok(())
+    }
+
+    pub fn is_added_by_bot(&self, playlist_id: &str) -> Result<bool> {
+        Ok(self.connection.query_row("\
+            SELECT EXISTS (SELECT 1 FROM PlaylistItems WHERE playlist_id = ?1 AND added_by_bot);
+        ", [playlist_id], |row| row.get(0))?)
+    }
+
+    /// Enable or disable fetching the playlist. Enabling it resets its failures.
+    /// Returns false if the playlist does not exist.
+    pub fn set_enabled(&self, playlist_id: &str, enabled: bool) -> Result<bool> {
+        let changed = self.connection.execute("\
+            UPDATE PlaylistItems
+            SET enabled = ?2, consecutive_failures = CASE WHEN ?2 THEN 0 ELSE consecutive_failures END
+            WHERE playlist_id = ?1;
+        ", (playlist_id, enabled))?;
+        Ok(changed > 0)
+    }
+
+    pub fn query_statuses(&self) -> Result<Vec<PlaylistStatus>> {
+        let mut statement = self.connection.prepare("\
+            SELECT playlist_id, uploader, datetime(last_success, 'unixepoch'), last_error, consecutive_failures, enabled
+            FROM PlaylistItems
+            ORDER BY playlist_id;
+        ")?;
+
+        let result = statement.query_map([], |row| {
+            Ok(PlaylistStatus {
+                playlist_id: row.get(0)?,
+                uploader: row.get(1)?,
+                last_success: row.get(2)?,
+                last_error: row.get(3)?,
+                consecutive_failures: row.get(4)?,
+                enabled: row.get(5)?,
+            })
+        })?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Add the video of the given item to the history, if it was never seen in its playlist before.
+    /// The first seen timestamp is the current time.
+    pub fn add_to_history(&self, item: &PlaylistItem, notified: bool) -> Result<()> {
+        self.connection.execute("\
+            INSERT OR IGNORE INTO Videos (video_id, playlist_id, title, uploader, duration, first_seen, notified, kind, release_timestamp, short)
+            VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now'), ?6, ?7, ?8, ?9);
+        ", (
+            &item.video_id,
+            &item.playlist_id,
+            &item.title,
+            &item.uploader,
+            &format!("{}", item.duration),
+            notified,
+            item.kind,
+            item.release_timestamp,
+            item.short
         ))?;
 
         Ok(())
     }
 
+    /// Get the kind a video of the playlist had when it was last seen, or None if it is not part of the history
+    pub fn get_history_kind(&self, playlist_id: &str, video_id: &str) -> Result<Option<VideoKind>> {
+        Ok(self.connection.query_row("\
+            SELECT kind FROM Videos WHERE playlist_id = ?1 AND video_id = ?2;
+        ", [playlist_id, video_id], |row| row.get(0)).optional()?)
+    }
+
+    /// Store the current kind of a video from the history, like when a livestream started or ended
+    pub fn update_history_kind(&self, item: &PlaylistItem) -> Result<()> {
+        self.connection.execute("\
+            UPDATE Videos SET kind = ?3, release_timestamp = ?4, duration = ?5 WHERE playlist_id = ?1 AND video_id = ?2;
+        ", (
+            &item.playlist_id,
+            &item.video_id,
+            item.kind,
+            item.release_timestamp,
+            &format!("{}", item.duration)
+        ))?;
+        Ok(())
+    }
+
+    /// Set if new videos of the given kind from the playlist should be sent to the user, or to everyone if there is no user
+    pub fn set_kind_notification(&self, user_id: Option<i64>, playlist_id: &str, kind: VideoKind, notify: bool) -> Result<()> {
+        self.connection.execute("\
+            INSERT OR REPLACE INTO KindNotifications (user_id, playlist_id, kind, notify) VALUES (?1, ?2, ?3, ?4);
+        ", (user_id.unwrap_or(EVERYONE), playlist_id, kind, notify))?;
+        Ok(())
+    }
+
+    /// Get the kinds of videos of the playlist the user does not want to be notified about.
+    /// Without a user, the kinds nobody is notified about are returned
+    pub fn get_muted_kinds(&self, user_id: Option<i64>, playlist_id: &str) -> Result<Vec<VideoKind>> {
+        let mut statement = self.connection.prepare("\
+            SELECT kind FROM KindNotifications WHERE user_id = ?1 AND playlist_id = ?2 AND NOT notify;
+        ")?;
+
+        let result = statement.query_map((user_id.unwrap_or(EVERYONE), playlist_id), |row| row.get(0))?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Query all videos from the history, newest first.
+    ///
+    /// Can be filtered by the channel which uploaded the video and by a date (or any other
+    /// time value SQLite understands, like 'YYYY-MM-DD') the video must have been seen after.
+    pub fn query_history(&self, channel: Option<&str>, since: Option<&str>) -> Result<Vec<PlaylistItem>> {
+        let mut statement = self.connection.prepare("\
+            SELECT playlist_id, video_id, title, duration, uploader, kind, release_timestamp, short FROM Videos
+            WHERE (?1 IS NULL OR uploader = ?1)
+            AND (?2 IS NULL OR first_seen >= CAST(strftime('%s', ?2) AS INTEGER))
+            ORDER BY first_seen DESC;
+        ")?;
+
+        let result = statement.query_map((channel, since), |row| {
+            Ok(PlaylistItem {
+                playlist_id: row.get(0)?,
+                video_id: row.get(1)?,
+                title: row.get(2)?,
+                duration: row.get(3)?,
+                uploader: row.get(4)?,
+                // the history does not track the order of videos in the playlist
+                previous_video_id: String::new(),
+                kind: row.get(5)?,
+                release_timestamp: row.get(6)?,
+                short: row.get(7)?,
+            })
+        })?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Query the latest finished videos of the playlist from the history, newest first.
+    /// Includes the path of the downloaded file, if the video was downloaded.
+    pub fn query_feed_items(&self, playlist_id: &str, limit: usize) -> Result<Vec<FeedItem>> {
+        let mut statement = self.connection.prepare("\
+            SELECT v.playlist_id, v.video_id, v.title, v.duration, v.uploader, v.kind, v.release_timestamp, v.short, v.first_seen, d.file_path
+            FROM Videos v
+            LEFT JOIN Downloads d ON d.video_id = v.video_id AND d.status = 'done'
+            WHERE v.playlist_id = ?1 AND v.kind IN ('upload', 'past_live')
+            ORDER BY v.first_seen DESC
+            LIMIT ?2;
+        ")?;
+
+        let result = statement.query_map((playlist_id, limit), |row| {
+            Ok(FeedItem {
+                video: PlaylistItem {
+                    playlist_id: row.get(0)?,
+                    video_id: row.get(1)?,
+                    title: row.get(2)?,
+                    duration: row.get(3)?,
+                    uploader: row.get(4)?,
+                    previous_video_id: String::new(),
+                    kind: row.get(5)?,
+                    release_timestamp: row.get(6)?,
+                    short: row.get(7)?,
+                },
+                first_seen: row.get(8)?,
+                file_path: row.get(9)?,
+            })
+        })?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
     pub fn delete(&self, id: &str) -> Result<()> {
         self.connection.execute("DELETE FROM PlaylistItems WHERE playlist_id = ?1", &[id])?;
+        self.connection.execute("DELETE FROM Subscriptions WHERE playlist_id = ?1", &[id])?;
+        self.connection.execute("DELETE FROM KindNotifications WHERE playlist_id = ?1", &[id])?;
+        self.connection.execute("DELETE FROM Filters WHERE playlist_id = ?1", &[id])?;
+        self.connection.execute("DELETE FROM FeedCache WHERE playlist_id = ?1", [id])?;
+        Ok(())
+    }
+
+    pub fn get_feed_cache(&self, playlist_id: &str) -> Result<Option<FeedCache>> {
+        Ok(self.connection.query_row("\
+            SELECT etag, last_modified, items FROM FeedCache WHERE playlist_id = ?1;
+        ", [playlist_id], |row| Ok(FeedCache {
+            etag: row.get(0)?,
+            last_modified: row.get(1)?,
+            items: serde_json::from_str(&row.get::<_, String>(2)?)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, Type::Text, e.into()))?,
+        })).optional()?)
+    }
+
+    /// Add the feed cache of the playlist or replace the existing one
+    pub fn set_feed_cache(&self, playlist_id: &str, cache: &FeedCache) -> Result<()> {
+        self.connection.execute("\
+            INSERT OR REPLACE INTO FeedCache (playlist_id, etag, last_modified, items) VALUES (?1, ?2, ?3, ?4);
+        ", (
+            playlist_id,
+            &cache.etag,
+            &cache.last_modified,
+            serde_json::to_string(&cache.items).expect("the items should be serializable")
+        ))?;
+        Ok(())
+    }
+
+    /// Add the filter of the user, or for everyone if there is no user. Returns the id of the filter.
+    pub fn add_filter(&self, user_id: Option<i64>, playlist_id: &str, rule: &FilterRule) -> Result<i64> {
+        self.connection.execute("\
+            INSERT INTO Filters (playlist_id, kind, value, user_id) VALUES (?1, ?2, ?3, ?4);
+        ", (playlist_id, rule.kind(), rule.value(), user_id.unwrap_or(EVERYONE)))?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Delete the filter with the given id. Returns false if it does not exist.
+    pub fn delete_filter(&self, filter_id: i64) -> Result<bool> {
+        let deleted = self.connection.execute("DELETE FROM Filters WHERE filter_id = ?1", [filter_id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Get the filters of all users for the given playlist, or of all playlists if no id is given
+    pub fn get_filters(&self, playlist_id: Option<&str>) -> Result<Vec<Filter>> {
+        let mut statement = self.connection.prepare("\
+            SELECT filter_id, playlist_id, kind, value, user_id FROM Filters
+            WHERE ?1 IS NULL OR playlist_id = ?1
+            ORDER BY playlist_id, filter_id;
+        ")?;
+
+        let result = statement.query_map([playlist_id], |row| {
+            let kind: String = row.get(2)?;
+            let value: String = row.get(3)?;
+
+            Ok(Filter {
+                id: row.get(0)?,
+                playlist_id: row.get(1)?,
+                user_id: Some(row.get(4)?).filter(|user_id| *user_id != EVERYONE),
+                rule: FilterRule::parse(&kind, &value)
+                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, Type::Text, e.into()))?,
+            })
+        })?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Store the status of the download of a video. The file path and error are replaced too.
+    /// Every download which starts counts as an attempt.
+    pub fn set_download_status(
+        &self,
+        video_id: &str,
+        playlist_id: &str,
+        status: DownloadStatus,
+        file_path: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        self.connection.execute("\
+            INSERT INTO Downloads (video_id, playlist_id, status, file_path, error, updated, attempts)
+            VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now'), ?3 = 'downloading')
+            ON CONFLICT (video_id) DO UPDATE SET
+                status = excluded.status,
+                file_path = excluded.file_path,
+                error = excluded.error,
+                updated = excluded.updated,
+                attempts = attempts + excluded.attempts;
+        ", (video_id, playlist_id, status, file_path, error))?;
+        Ok(())
+    }
+
+    /// Mark the queued and running downloads of the given videos as still being worked on
+    pub fn keep_downloads_alive(&self, video_ids: &[String]) -> Result<()> {
+        for video_id in video_ids {
+            self.connection.execute("\
+                UPDATE Downloads SET updated = strftime('%s', 'now')
+                WHERE video_id = ?1 AND status IN ('queued', 'downloading');
+            ", [video_id])?;
+        }
+        Ok(())
+    }
+
+    /// Hand the queued and running downloads of the given videos back, so the next downloader takes them over right away
+    pub fn release_downloads(&self, video_ids: &[String]) -> Result<()> {
+        for video_id in video_ids {
+            self.connection.execute("\
+                UPDATE Downloads SET status = 'queued', updated = 0
+                WHERE video_id = ?1 AND status IN ('queued', 'downloading');
+            ", [video_id])?;
+        }
+        Ok(())
+    }
+
+    /// Queue the downloads again which nobody works on anymore: queued or running downloads which were not kept alive
+    /// for the given seconds, and failed downloads with less than the given attempts whose last attempt is at least the
+    /// retry delay ago. Returns the (video id, playlist id) of every download which was queued again.
+    ///
+    /// Queueing a download again and selecting it happens in a single statement, so no two downloaders take the same one.
+    pub fn requeue_abandoned_downloads(&self, abandoned_after: u64, retry_delay: u64, max_attempts: u32) -> Result<Vec<(String, String)>> {
+        let mut statement = self.connection.prepare("\
+            UPDATE Downloads SET status = 'queued', updated = strftime('%s', 'now')
+            WHERE (status IN ('queued', 'downloading') AND updated <= strftime('%s', 'now') - ?1)
+                OR (status = 'failed' AND attempts < ?3 AND updated <= strftime('%s', 'now') - ?2)
+            RETURNING video_id, playlist_id;
+        ")?;
+
+        let result = statement.query_map((abandoned_after, retry_delay, max_attempts), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    pub fn get_download_status(&self, video_id: &str) -> Result<Option<DownloadStatus>> {
+        Ok(self.connection.query_row("\
+            SELECT status FROM Downloads WHERE video_id = ?1;
+        ", [video_id], |row| row.get(0)).optional()?)
+    }
+
+    /// Query all downloads with the given status, or all of them if no status is given. Newest first
+    pub fn query_downloads(&self, status: Option<DownloadStatus>) -> Result<Vec<Download>> {
+        let mut statement = self.connection.prepare("\
+            SELECT d.video_id, d.playlist_id, v.title, d.status, d.file_path, d.error FROM Downloads d
+            LEFT JOIN Videos v ON v.playlist_id = d.playlist_id AND v.video_id = d.video_id
+            WHERE ?1 IS NULL OR d.status = ?1
+            ORDER BY d.updated DESC;
+        ")?;
+
+        let result = statement.query_map([status], |row| {
+            Ok(Download {
+                video_id: row.get(0)?,
+                playlist_id: row.get(1)?,
+                title: row.get(2)?,
+                status: row.get(3)?,
+                file_path: row.get(4)?,
+                error: row.get(5)?,
+            })
+        })?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    pub fn get_user(&self, user_id: i64) -> Result<Option<User>> {
+        Ok(self.connection.query_row("\
+            SELECT user_id, username, chat_id, approved, admin, links FROM Users WHERE user_id = ?1;
+        ", [user_id], Self::row_to_user).optional()?)
+    }
+
+    pub fn get_user_by_name(&self, username: &str) -> Result<Option<User>> {
+        Ok(self.connection.query_row("\
+            SELECT user_id, username, chat_id, approved, admin, links FROM Users WHERE username = ?1;
+        ", [username], Self::row_to_user).optional()?)
+    }
+
+    pub fn get_users(&self) -> Result<Vec<User>> {
+        let mut statement = self.connection.prepare("\
+            SELECT user_id, username, chat_id, approved, admin, links FROM Users ORDER BY username;
+        ")?;
+
+        let result = statement.query_map([], Self::row_to_user)?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    fn row_to_user(row: &Row) -> rusqlite::Result<User> {
+        Ok(User {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            chat_id: row.get(2)?,
+            approved: row.get(3)?,
+            admin: row.get(4)?,
+            links: row
+                .get::<_, Option<String>>(5)?
+                .map(|links| parse_link_styles(&links))
+                .transpose()
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(5, Type::Text, e.into()))?,
+        })
+    }
+
+    /// Add the user or update it if it already exists
+    pub fn add_user(&self, user: &User) -> Result<()> {
+        self.connection.execute("\
+            INSERT OR REPLACE INTO Users (user_id, username, chat_id, approved, admin, links)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        ", (
+            user.id,
+            &user.username,
+            user.chat_id,
+            user.approved,
+            user.admin,
+            user.links.as_deref().map(format_link_styles)
+        ))?;
+
+        Ok(())
+    }
+
+    /// Delete the user and all of its subscriptions
+    pub fn delete_user(&self, user_id: i64) -> Result<()> {
+        self.connection.execute("DELETE FROM Users WHERE user_id = ?1", [user_id])?;
+        self.connection.execute("DELETE FROM Subscriptions WHERE user_id = ?1", [user_id])?;
+        self.connection.execute("DELETE FROM KindNotifications WHERE user_id = ?1", [user_id])?;
+        self.connection.execute("DELETE FROM Filters WHERE user_id = ?1", [user_id])?;
+        Ok(())
+    }
+
+    pub fn add_subscription(&self, user_id: i64, playlist_id: &str) -> Result<()> {
+        self.connection.execute("\
+            INSERT OR IGNORE INTO Subscriptions (user_id, playlist_id) VALUES (?1, ?2);
+        ", (user_id, playlist_id))?;
         Ok(())
     }
+
+    /// Returns false if the user was not subscribed to the playlist
+    pub fn delete_subscription(&self, user_id: i64, playlist_id: &str) -> Result<bool> {
+        let deleted = self.connection.execute("\
+            DELETE FROM Subscriptions WHERE user_id = ?1 AND playlist_id = ?2;
+        ", (user_id, playlist_id))?;
+        Ok(deleted > 0)
+    }
+
+    /// Get the ids of all playlists the user subscribed to
+    pub fn get_subscriptions(&self, user_id: i64) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare("\
+            SELECT playlist_id FROM Subscriptions WHERE user_id = ?1 ORDER BY playlist_id;
+        ")?;
+
+        let result = statement.query_map([user_id], |row| row.get(0))?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Check if any user (approved or not) subscribed to the playlist
+    pub fn has_subscribers(&self, playlist_id: &str) -> Result<bool> {
+        Ok(self.connection.query_row("\
+            SELECT EXISTS (SELECT 1 FROM Subscriptions WHERE playlist_id = ?1);
+        ", [playlist_id], |row| row.get(0))?)
+    }
+
+    /// Get all approved users which subscribed to the playlist
+    pub fn get_subscribers(&self, playlist_id: &str) -> Result<Vec<User>> {
+        let mut statement = self.connection.prepare("\
+            SELECT Users.user_id, username, chat_id, approved, admin, links FROM Subscriptions
+            JOIN Users ON Users.user_id = Subscriptions.user_id
+            WHERE Subscriptions.playlist_id = ?1 AND Users.approved;
+        ")?;
+
+        let result = statement.query_map([playlist_id], Self::row_to_user)?;
+        Ok(result.collect::<std::result::Result<_, _>>()?)
+    }
 }
 
 #[error(message = "Error while connecting to the database or while executing queries: {self.0}", impl_from)]
-pub struct DBError(rusqlite::Error);
\ No newline at end of file
+pub struct DBError(rusqlite::Error);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYLIST: &str = "UUaaaaaaaaaaaaaaaaaaaaaa";
+
+    fn download_status(database: &Database, video_id: &str) -> (DownloadStatus, u32) {
+        database.connection.query_row("\
+            SELECT status, attempts FROM Downloads WHERE video_id = ?1;
+        ", [video_id], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+    }
+
+    /// Pretend the download was last updated the given seconds ago
+    fn age(database: &Database, video_id: &str, seconds: u64) {
+        database.connection.execute("\
+            UPDATE Downloads SET updated = strftime('%s', 'now') - ?2 WHERE video_id = ?1;
+        ", (video_id, seconds)).unwrap();
+    }
+
+    fn fail(database: &Database, video_id: &str) {
+        database.set_download_status(video_id, PLAYLIST, DownloadStatus::Downloading, None, None).unwrap();
+        database.set_download_status(video_id, PLAYLIST, DownloadStatus::Failed, None, Some("HTTP Error 403")).unwrap();
+    }
+
+    #[test]
+    fn the_history_keeps_a_video_for_every_playlist_it_is_part_of() {
+        const OTHER_PLAYLIST: &str = "PLbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        let database = Database::open_in_memory().unwrap();
+        let video = PlaylistItem {
+            playlist_id: PLAYLIST.to_string(),
+            video_id: "a5xvRqg9yDc".to_string(),
+            title: "Building a workbench, part 3".to_string(),
+            duration: 1312.0,
+            uploader: "Wood Works".to_string(),
+            previous_video_id: String::new(),
+            kind: VideoKind::Upcoming,
+            release_timestamp: None,
+            short: false,
+        };
+        let in_other_playlist = PlaylistItem { playlist_id: OTHER_PLAYLIST.to_string(), ..video.clone() };
+
+        database.add_to_history(&video, true).unwrap();
+        database.add_to_history(&in_other_playlist, true).unwrap();
+        database.update_history_kind(&PlaylistItem { kind: VideoKind::Live, ..video.clone() }).unwrap();
+
+        let playlists = database.query_history(None, None).unwrap().into_iter().map(|video| video.playlist_id).collect::<Vec<_>>();
+        assert_eq!(playlists.len(), 2);
+        assert!(playlists.contains(&OTHER_PLAYLIST.to_string()));
+        assert_eq!(database.get_history_kind(PLAYLIST, "a5xvRqg9yDc").unwrap(), Some(VideoKind::Live));
+        assert_eq!(database.get_history_kind(OTHER_PLAYLIST, "a5xvRqg9yDc").unwrap(), Some(VideoKind::Upcoming));
+    }
+
+    #[test]
+    fn every_started_download_is_an_attempt() {
+        let database = Database::open_in_memory().unwrap();
+
+        database.set_download_status("a5xvRqg9yDc", PLAYLIST, DownloadStatus::Queued, None, None).unwrap();
+        assert_eq!(download_status(&database, "a5xvRqg9yDc"), (DownloadStatus::Queued, 0));
+
+        fail(&database, "a5xvRqg9yDc");
+        database.set_download_status("a5xvRqg9yDc", PLAYLIST, DownloadStatus::Downloading, None, None).unwrap();
+        assert_eq!(download_status(&database, "a5xvRqg9yDc"), (DownloadStatus::Downloading, 2));
+    }
+
+    #[test]
+    fn only_abandoned_downloads_are_queued_again() {
+        let database = Database::open_in_memory().unwrap();
+        for video_id in ["running", "abandoned", "kept_alive"] {
+            database.set_download_status(video_id, PLAYLIST, DownloadStatus::Downloading, None, None).unwrap();
+            age(&database, video_id, 600);
+        }
+        age(&database, "running", 10);
+        database.keep_downloads_alive(&["kept_alive".to_string()]).unwrap();
+
+        let requeued = database.requeue_abandoned_downloads(300, 3600, 3).unwrap();
+
+        assert_eq!(requeued, [("abandoned".to_string(), PLAYLIST.to_string())]);
+        assert_eq!(download_status(&database, "abandoned").0, DownloadStatus::Queued);
+        // the download was taken over, so no other downloader queues it too
+        assert!(database.requeue_abandoned_downloads(300, 3600, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn released_downloads_are_queued_again_right_away() {
+        let database = Database::open_in_memory().unwrap();
+        database.set_download_status("a5xvRqg9yDc", PLAYLIST, DownloadStatus::Downloading, None, None).unwrap();
+
+        database.release_downloads(&["a5xvRqg9yDc".to_string()]).unwrap();
+
+        assert_eq!(database.requeue_abandoned_downloads(300, 3600, 3).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_downloads_are_retried_a_few_times() {
+        let database = Database::open_in_memory().unwrap();
+        for video_id in ["failed_once", "failed_just_now", "failed_too_often"] {
+            fail(&database, video_id);
+        }
+        fail(&database, "failed_too_often");
+        fail(&database, "failed_too_often");
+        age(&database, "failed_once", 7200);
+        age(&database, "failed_too_often", 7200);
+
+        let requeued = database.requeue_abandoned_downloads(300, 3600, 3).unwrap();
+
+        assert_eq!(requeued, [("failed_once".to_string(), PLAYLIST.to_string())]);
+        assert_eq!(download_status(&database, "failed_too_often"), (DownloadStatus::Failed, 3));
+    }
+}
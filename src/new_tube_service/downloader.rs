@@ -0,0 +1,285 @@
+use std::collections::HashSet;
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::config::{DownloadConfig, DownloadMode};
+use crate::download::DownloadStatus;
+use crate::new_tube_service::database::{DBError, Database};
+use crate::new_tube_service::yt_dlp;
+use crate::new_tube_service::FetchResult;
+use crate::playlist_item::{PlaylistItem, VideoKind};
+use crate::shutdown;
+
+/// The name of the yt-dlp download archive in the library directory
+const ARCHIVE_FILE_NAME: &str = "archive.txt";
+/// How often the downloads of this process are marked as still being worked on
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+/// After how many seconds without being kept alive a queued or running download is taken over by another process
+const ABANDONED_AFTER: u64 = 5 * 60;
+/// How many seconds to wait before a failed download is tried again
+const RETRY_DELAY: u64 = 60 * 60;
+/// How often a video is tried to be downloaded before it stays failed
+const MAX_ATTEMPTS: u32 = 3;
+
+/// A video waiting to be downloaded
+struct DownloadJob {
+    video_id: String,
+    playlist_id: String,
+    mode: DownloadMode,
+}
+
+/// Downloads new videos in the background, using yt-dlp.
+///
+/// The videos are queued and downloaded by a fixed amount of worker threads, so queueing never blocks.
+/// The status of every download is stored in the database. Downloaded videos are recorded in a
+/// yt-dlp download archive in the library directory, so they are never downloaded twice.
+///
+/// The bot and the CLI might download at the same time. The queued and running downloads of a downloader are
+/// kept alive in the database, so other downloaders only take over the ones which were abandoned.
+/// Failed downloads are tried again a few times.
+pub struct Downloader {
+    config: DownloadConfig,
+    database: Mutex<Database>,
+    sender: Mutex<Option<Sender<DownloadJob>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    /// The ids of the videos this downloader queued and did not finish yet
+    unfinished: Arc<Mutex<HashSet<String>>>,
+    /// Stops the thread which keeps the unfinished downloads alive once it is dropped
+    keep_alive_stop: Mutex<Option<Sender<()>>>,
+    keep_alive: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Downloader {
+    /// Start the download workers. Downloads which were abandoned by a stopped new_tube and failed downloads
+    /// which might be tried again are queued right away.
+    pub fn new(config: &DownloadConfig) -> Result<Self, DBError> {
+        let (sender, receiver) = channel::<DownloadJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let unfinished = Arc::new(Mutex::new(HashSet::new()));
+
+        let workers = (0..config.max_parallel_downloads.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                let config = config.clone();
+                let unfinished = unfinished.clone();
+                thread::spawn(move || Self::work(&config, &receiver, &unfinished))
+            })
+            .collect();
+
+        let (keep_alive_stop, keep_alive_receiver) = channel();
+        let keep_alive_unfinished = unfinished.clone();
+        let keep_alive = thread::spawn(move || Self::keep_alive(&keep_alive_unfinished, &keep_alive_receiver));
+
+        let downloader = Downloader {
+            config: config.clone(),
+            database: Mutex::new(Database::open()?),
+            sender: Mutex::new(Some(sender)),
+            workers: Mutex::new(workers),
+            unfinished,
+            keep_alive_stop: Mutex::new(Some(keep_alive_stop)),
+            keep_alive: Mutex::new(Some(keep_alive)),
+        };
+        downloader.requeue_abandoned()?;
+
+        Ok(downloader)
+    }
+
+    /// Queue the new videos of the fetch result whose playlists should be downloaded, including the muted ones.
+    /// Videos without a finished recording (like upcoming or running livestreams) are skipped.
+    /// Abandoned downloads and failed downloads which might be tried again are queued too.
+    /// Returns the amount of queued videos.
+    pub fn enqueue_new_videos(&self, result: &FetchResult) -> Result<usize, DBError> {
+        let videos = result.new_videos
+            .iter()
+            .chain(result.separate_chat_videos.iter().map(|(_, video)| video))
+            .chain(result.muted_videos.iter())
+            .filter(|video| matches!(video.kind, VideoKind::Upload | VideoKind::PastLive));
+        let mut amount = self.requeue_abandoned()?;
+
+        for video in videos {
+            if self.enqueue(video)? {
+                amount += 1
+            }
+        }
+
+        Ok(amount)
+    }
+
+    /// Queue the video if its playlist should be downloaded and it was not queued or downloaded before
+    fn enqueue(&self, video: &PlaylistItem) -> Result<bool, DBError> {
+        let Some(mode) = self.config.playlists.get(&video.playlist_id).copied() else {
+            return Ok(false);
+        };
+
+        let database = self.database.lock().expect("the database should not be poisoned");
+
+        if database.get_download_status(&video.video_id)?.is_some_and(|status| status != DownloadStatus::Failed) {
+            return Ok(false);
+        }
+
+        database.set_download_status(&video.video_id, &video.playlist_id, DownloadStatus::Queued, None, None)?;
+        self.send(DownloadJob {
+            video_id: video.video_id.clone(),
+            playlist_id: video.playlist_id.clone(),
+            mode,
+        });
+
+        Ok(true)
+    }
+
+    /// Queue the downloads nobody works on anymore and the failed ones which might be tried again.
+    /// Returns the amount of queued videos.
+    fn requeue_abandoned(&self) -> Result<usize, DBError> {
+        let database = self.database.lock().expect("the database should not be poisoned");
+        let mut amount = 0;
+
+        for (video_id, playlist_id) in database.requeue_abandoned_downloads(ABANDONED_AFTER, RETRY_DELAY, MAX_ATTEMPTS)? {
+            // the playlist might not be downloaded anymore
+            let Some(mode) = self.config.playlists.get(&playlist_id).copied() else {
+                continue;
+            };
+
+            self.send(DownloadJob {
+                video_id,
+                playlist_id,
+                mode,
+            });
+            amount += 1;
+        }
+
+        Ok(amount)
+    }
+
+    fn send(&self, job: DownloadJob) {
+        if let Some(sender) = self.sender.lock().expect("the sender should not be poisoned").as_ref() {
+            self.unfinished.lock().expect("the unfinished downloads should not be poisoned").insert(job.video_id.clone());
+            sender.send(job).expect("the download workers should be running")
+        }
+    }
+
+    /// Wait until all queued videos are downloaded. Nothing can be queued afterwards.
+    ///
+    /// If new_tube is stopping, only the running downloads are waited for. The queued ones are handed back,
+    /// so the next start downloads them.
+    pub fn finish(&self) {
+        // the workers stop once the queue is empty and the sender is gone
+        self.sender.lock().expect("the sender should not be poisoned").take();
+
+        for worker in self.workers.lock().expect("the workers should not be poisoned").drain(..) {
+            let _ = worker.join();
+        }
+
+        self.keep_alive_stop.lock().expect("the keep alive sender should not be poisoned").take();
+
+        if let Some(keep_alive) = self.keep_alive.lock().expect("the keep alive thread should not be poisoned").take() {
+            let _ = keep_alive.join();
+        }
+
+        let unfinished: Vec<String> = self.unfinished.lock().expect("the unfinished downloads should not be poisoned").drain().collect();
+        let released = self.database.lock().expect("the database should not be poisoned").release_downloads(&unfinished);
+
+        if let Err(err) = released {
+            println!("failed to hand back the unfinished downloads: {err}")
+        }
+    }
+
+    /// Mark the unfinished downloads as being worked on, until the stop sender is dropped
+    fn keep_alive(unfinished: &Mutex<HashSet<String>>, stop: &Receiver<()>) {
+        let database = match Database::open() {
+            Ok(database) => database,
+            Err(err) => return println!("the downloads could not be kept alive, as the database could not be opened: {err}")
+        };
+
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            let video_ids: Vec<String> = unfinished.lock().expect("the unfinished downloads should not be poisoned").iter().cloned().collect();
+
+            if let Err(err) = database.keep_downloads_alive(&video_ids) {
+                println!("failed to keep the downloads alive: {err}")
+            }
+        }
+    }
+
+    /// Download the queued videos until the queue is closed. Once new_tube is stopping, the remaining jobs are skipped.
+    fn work(config: &DownloadConfig, receiver: &Mutex<Receiver<DownloadJob>>, unfinished: &Mutex<HashSet<String>>) {
+        let database = match Database::open() {
+            Ok(database) => database,
+            Err(err) => return println!("download worker could not open the database: {err}")
+        };
+
+        loop {
+            // the lock must be released before downloading, so other workers can take jobs
+            let job = receiver.lock().expect("the receiver should not be poisoned").recv();
+            let Ok(job) = job else {
+                return;
+            };
+
+            if shutdown::requested() {
+                continue;
+            }
+
+            match Self::download(config, &database, &job) {
+                Ok(true) => {
+                    unfinished.lock().expect("the unfinished downloads should not be poisoned").remove(&job.video_id);
+                }
+                Ok(false) => {}
+                Err(err) => println!("failed to store the download status of video {}: {err}", job.video_id)
+            }
+        }
+    }
+
+    /// Download the video and store the outcome. Returns false if the download was interrupted by the shutdown,
+    /// so it is still unfinished.
+    fn download(config: &DownloadConfig, database: &Database, job: &DownloadJob) -> Result<bool, DBError> {
+        database.set_download_status(&job.video_id, &job.playlist_id, DownloadStatus::Downloading, None, None)?;
+
+        match Self::execute_download(config, job) {
+            Ok(file_path) => {
+                println!("Downloaded video {}", job.video_id);
+                database.set_download_status(&job.video_id, &job.playlist_id, DownloadStatus::Done, file_path.as_deref(), None)?;
+                Ok(true)
+            }
+            // yt-dlp was killed, as new_tube is stopping
+            Err(_) if shutdown::requested() => Ok(false),
+            Err(error) => {
+                println!("Failed to download video {}: {error}", job.video_id);
+                database.set_download_status(&job.video_id, &job.playlist_id, DownloadStatus::Failed, None, Some(&error))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Download the video with yt-dlp. Returns the path of the downloaded file,
+    /// or None if the video is already part of the download archive.
+    ///
+    /// Example: yt-dlp https://www.youtube.com/watch?v=<VIDEO_ID> --quiet --no-simulate --print after_move:filepath -P <LIBRARY_DIR> -o <TEMPLATE> -f <FORMAT> --download-archive <LIBRARY_DIR>/archive.txt
+    fn execute_download(config: &DownloadConfig, job: &DownloadJob) -> Result<Option<String>, String> {
+        let format = match job.mode {
+            DownloadMode::Video => &config.video_format,
+            DownloadMode::Audio => &config.audio_format,
+        };
+
+        let output = yt_dlp::run(Command::new("yt-dlp")
+            .arg(format!("https://www.youtube.com/watch?v={}", job.video_id))
+            .arg("--quiet")
+            .arg("--no-progress")
+            .arg("--no-simulate")
+            .arg("--print")
+            .arg("after_move:filepath")
+            .arg("--paths")
+            .arg(&config.library_dir)
+            .arg("--output")
+            .arg(&config.file_name_template)
+            .arg("--format")
+            .arg(format)
+            .arg("--download-archive")
+            .arg(config.library_dir.join(ARCHIVE_FILE_NAME)), Duration::from_secs(config.timeout))
+            .map_err(|err| err.to_string())?;
+
+        let file_path = String::from_utf8_lossy(&output.stdout).lines().next().map(|line| line.trim().to_string());
+        Ok(file_path.filter(|path| !path.is_empty()))
+    }
+}
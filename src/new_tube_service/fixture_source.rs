@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::new_tube_service::video_source::{Result, VideoSource, VideoSourceError};
+use crate::new_tube_service::yt_dlp;
+use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+/// The items of every playlist, newest first. None if fetching the playlist fails
+type Playlists = HashMap<String, Option<Vec<YTDLPItem>>>;
+
+/// A video source which returns fixed items instead of asking YouTube, so the service can be tested offline.
+/// Clones share their items, so a test can change them after the service got the source.
+#[derive(Clone, Default)]
+pub struct FixtureSource {
+    playlists: Arc<Mutex<Playlists>>,
+}
+
+impl FixtureSource {
+    /// Create the source from saved yt-dlp output, which might contain the items of multiple playlists
+    pub fn from_yt_dlp_output(output: &str) -> Self {
+        let source = FixtureSource::default();
+
+        for item in yt_dlp::parse_items(output).expect("the fixture should contain valid items") {
+            source.playlists()
+                .entry(item.playlist_id.clone())
+                .or_default()
+                .get_or_insert_with(Vec::new)
+                .push(item);
+        }
+
+        source
+    }
+
+    fn playlists(&self) -> MutexGuard<'_, Playlists> {
+        self.playlists.lock().expect("the fixture should not be poisoned")
+    }
+
+    /// Replace the items of the playlist, newest first
+    pub fn set_items(&self, playlist_id: &str, items: Vec<YTDLPItem>) {
+        self.playlists().insert(playlist_id.to_string(), Some(items));
+    }
+
+    /// Let every further fetch of the playlist fail
+    pub fn set_failing(&self, playlist_id: &str) {
+        self.playlists().insert(playlist_id.to_string(), None);
+    }
+
+    /// The items of the playlist, newest first
+    pub fn items(&self, playlist_id: &str) -> Vec<YTDLPItem> {
+        self.playlists().get(playlist_id).cloned().flatten().unwrap_or_default()
+    }
+}
+
+impl VideoSource for FixtureSource {
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>> {
+        match self.playlists().get(playlist_id) {
+            Some(Some(items)) => Ok(items.iter().take(amount).cloned().collect()),
+            Some(None) => Err(VideoSourceError::UnexpectedResponse(format!("fetching {playlist_id} fails"))),
+            None => Err(VideoSourceError::UnexpectedResponse(format!("the playlist {playlist_id} is not part of the fixture")))
+        }
+    }
+}
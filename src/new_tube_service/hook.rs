@@ -0,0 +1,254 @@
+use std::io;
+use std::io::Write;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use error_generator::error;
+
+use crate::config::HookConfig;
+use crate::link::LinkStyle;
+use crate::new_tube_service::process::RunningProcess;
+use crate::playlist_item::PlaylistItem;
+use crate::shutdown;
+
+type Result<T> = std::result::Result<T, HookError>;
+
+/// A hook which failed for a video
+pub struct HookFailure {
+    /// The command of the hook
+    pub command: String,
+    pub video_id: String,
+    pub error: HookError,
+    /// If the user wants to be notified about the failure
+    pub notify: bool,
+}
+
+/// A new video the hooks must run for
+struct HookJob {
+    video: PlaylistItem,
+    notified: bool,
+}
+
+/// Runs the hooks for the new videos on a worker thread, so slow hooks do not delay fetching and notifying.
+///
+/// The videos are queued and handled one after another. Failed hooks are collected until they are taken.
+pub struct HookRunner {
+    sender: Mutex<Option<Sender<HookJob>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    failures: Arc<Mutex<Vec<HookFailure>>>,
+}
+
+impl HookRunner {
+    /// Start the worker. Without hooks, no worker is needed and nothing is ever queued.
+    pub fn new(hooks: Vec<HookConfig>, links: Vec<LinkStyle>) -> Self {
+        let failures = Arc::new(Mutex::new(vec![]));
+
+        if hooks.is_empty() {
+            return HookRunner {
+                sender: Mutex::new(None),
+                worker: Mutex::new(None),
+                failures,
+            };
+        }
+
+        let (sender, receiver) = channel::<HookJob>();
+        let worker_failures = failures.clone();
+        let worker = thread::spawn(move || {
+            for job in receiver {
+                // no hooks are started once new_tube is stopping
+                if shutdown::requested() {
+                    continue;
+                }
+
+                let failures = run_hooks(&hooks, &job.video, &links, job.notified);
+                worker_failures.lock().expect("the hook failures should not be poisoned").extend(failures);
+            }
+        });
+
+        HookRunner {
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(worker)),
+            failures,
+        }
+    }
+
+    /// Queue the video, so the hooks run for it
+    pub fn enqueue(&self, video: PlaylistItem, notified: bool) {
+        if let Some(sender) = self.sender.lock().expect("the sender should not be poisoned").as_ref() {
+            sender.send(HookJob { video, notified }).expect("the hook worker should be running")
+        }
+    }
+
+    /// Get the hooks which failed since the last call
+    pub fn take_failures(&self) -> Vec<HookFailure> {
+        std::mem::take(&mut *self.failures.lock().expect("the hook failures should not be poisoned"))
+    }
+
+    /// Wait until the hooks ran for all queued videos. Nothing can be queued afterwards.
+    pub fn finish(&self) {
+        // the worker stops once the queue is empty and the sender is gone
+        self.sender.lock().expect("the sender should not be poisoned").take();
+
+        if let Some(worker) = self.worker.lock().expect("the worker should not be poisoned").take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Run all hooks for the given video, one after another.
+///
+/// Each hook gets the video as JSON on stdin. The most important fields are also provided as
+/// environment variables, prefixed with NEW_TUBE_. NEW_TUBE_LINK uses the first of the given link styles.
+/// NEW_TUBE_NOTIFIED tells if anyone is notified about the video, as muted and filtered videos are passed
+/// to the hooks too.
+///
+/// The exit status and stderr of every hook are logged. Failed hooks are returned.
+pub fn run_hooks(hooks: &[HookConfig], video: &PlaylistItem, links: &[LinkStyle], notified: bool) -> Vec<HookFailure> {
+    hooks
+        .iter()
+        .filter_map(|hook| match run_hook(hook, video, links, notified) {
+            Ok(()) => None,
+            Err(error) => {
+                println!("Hook '{}' failed for video {}: {error}", hook.command, video.video_id);
+                Some(HookFailure {
+                    command: hook.command.clone(),
+                    video_id: video.video_id.clone(),
+                    error,
+                    notify: hook.notify_on_failure,
+                })
+            }
+        })
+        .collect()
+}
+
+fn run_hook(hook: &HookConfig, video: &PlaylistItem, links: &[LinkStyle], notified: bool) -> Result<()> {
+    let mut process = RunningProcess::spawn(Command::new(&hook.command)
+        .args(&hook.args)
+        .env("NEW_TUBE_PLAYLIST_ID", &video.playlist_id)
+        .env("NEW_TUBE_VIDEO_ID", &video.video_id)
+        .env("NEW_TUBE_TITLE", &video.title)
+        .env("NEW_TUBE_UPLOADER", &video.uploader)
+        .env("NEW_TUBE_DURATION", video.duration.to_string())
+        .env("NEW_TUBE_LINK", video.link(links))
+        .env("NEW_TUBE_KIND", video.kind.as_str())
+        .env("NEW_TUBE_SHORT", video.short.to_string())
+        .env("NEW_TUBE_NOTIFIED", notified.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped()))?;
+
+    let json = serde_json::to_string(video).expect("a playlist item should be serializable");
+
+    // the hook might not care about its input and exit before reading it, so a failed write is ignored
+    if let Some(mut stdin) = process.stdin() {
+        let _ = stdin.write_all(json.as_bytes());
+    }
+
+    let Some(output) = process.wait_with_timeout(Duration::from_secs(hook.timeout))? else {
+        return Err(HookError::TimedOut(hook.timeout));
+    };
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+    println!("Hook '{}' for video {} exited with {}", hook.command, video.video_id, output.status);
+
+    if !stderr.is_empty() {
+        println!("stderr of hook '{}': {stderr}", hook.command);
+    }
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(HookError::Failed(output.status, stderr.to_string()))
+    }
+}
+
+#[error]
+pub enum HookError {
+    #[error(message = "Failed to start the hook: {_0}", impl_from)]
+    StartFailed(io::Error),
+    #[error(message = "The hook did not finish within {_0} seconds and was killed")]
+    TimedOut(u64),
+    #[error(message = "The hook exited with {_0}: {_1}")]
+    Failed(ExitStatus, String),
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+    fn video() -> PlaylistItem {
+        let item = YTDLPItem {
+            playlist_id: "UUaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            id: "a5xvRqg9yDc".to_string(),
+            title: "Video".to_string(),
+            duration: Some(600.0),
+            channel: "Wood Works".to_string(),
+            live_status: None,
+            release_timestamp: None,
+            url: None,
+            width: None,
+            height: None,
+        };
+        PlaylistItem::new(item, String::new())
+    }
+
+    fn hook(command: &str, args: &[&str], timeout: u64) -> HookConfig {
+        HookConfig {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            timeout,
+            notify_on_failure: true,
+        }
+    }
+
+    #[test]
+    fn failed_hooks_are_reported_with_their_stderr() {
+        let hooks = [hook("sh", &["-c", "echo \"broken $NEW_TUBE_VIDEO_ID\" >&2; exit 3"], 10), hook("true", &[], 10)];
+
+        let failures = run_hooks(&hooks, &video(), &[], true);
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].command, "sh");
+        assert!(matches!(&failures[0].error, HookError::Failed(status, stderr) if status.code() == Some(3) && stderr == "broken a5xvRqg9yDc"));
+    }
+
+    #[test]
+    fn hooks_which_take_too_long_are_killed() {
+        let start = Instant::now();
+
+        let failures = run_hooks(&[hook("sleep", &["10"], 1)], &video(), &[], false);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0].error, HookError::TimedOut(1)));
+    }
+
+    #[test]
+    fn the_runner_collects_the_failures_in_the_background() {
+        let runner = HookRunner::new(vec![hook("false", &[], 10)], vec![]);
+
+        runner.enqueue(video(), true);
+        runner.enqueue(video(), false);
+        runner.finish();
+
+        assert_eq!(runner.take_failures().len(), 2);
+        assert!(runner.take_failures().is_empty());
+    }
+
+    #[test]
+    fn the_runner_does_nothing_without_hooks() {
+        let runner = HookRunner::new(vec![], vec![]);
+
+        runner.enqueue(video(), true);
+        runner.finish();
+
+        assert!(runner.take_failures().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use serde::Deserialize;
+
+use crate::new_tube_service::video_source::{http_agent, Result, VideoSource};
+use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+/// A video source which uses the api of an Invidious instance (https://docs.invidious.io/api/).
+pub struct InvidiousSource {
+    agent: ureq::Agent,
+    /// The url of the instance, like https://yewtu.be
+    instance: String,
+}
+
+impl InvidiousSource {
+    pub fn new(instance: &str) -> Self {
+        InvidiousSource {
+            agent: http_agent(),
+            instance: instance.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl VideoSource for InvidiousSource {
+    // Example: GET https://yewtu.be/api/v1/playlists/<PLAYLIST_ID>
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>> {
+        let playlist: Playlist = self.agent
+            .get(&format!("{}/api/v1/playlists/{playlist_id}", self.instance))
+            .call()
+            .map_err(Box::new)?
+            .into_json()?;
+
+        Ok(playlist
+            .videos
+            .into_iter()
+            .take(amount)
+            .map(|video| video.into_item(playlist_id))
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct Playlist {
+    videos: Vec<PlaylistVideo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistVideo {
+    video_id: String,
+    title: String,
+    author: String,
+    /// Zero for running livestreams
+    length_seconds: u64,
+    #[serde(default)]
+    live_now: bool,
+    /// If the video is an upcoming livestream or premiere
+    #[serde(default)]
+    is_upcoming: bool,
+    /// When an upcoming premiere starts, as a unix timestamp
+    #[serde(default)]
+    premiere_timestamp: Option<i64>,
+    /// When the video was uploaded, as a unix timestamp. For upcoming videos, this is when they start
+    #[serde(default)]
+    published: Option<i64>,
+}
+
+impl PlaylistVideo {
+    fn into_item(self, playlist_id: &str) -> YTDLPItem {
+        YTDLPItem {
+            playlist_id: playlist_id.to_string(),
+            id: self.video_id,
+            title: self.title,
+            duration: (self.length_seconds > 0).then_some(self.length_seconds as f32),
+            channel: self.author,
+            // the same statuses yt-dlp uses. Finished livestreams cannot be told apart from uploads
+            live_status: match (self.live_now, self.is_upcoming) {
+                (true, _) => Some("is_live".to_string()),
+                (_, true) => Some("is_upcoming".to_string()),
+                _ => None
+            },
+            release_timestamp: self.is_upcoming.then(|| self.premiere_timestamp.or(self.published)).flatten(),
+            url: None,
+            width: None,
+            height: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::new_tube_service::video_source::VideoSourceError;
+    use crate::playlist_item::{PlaylistItem, VideoKind};
+
+    const PLAYLIST: &str = include_str!("../../tests/fixtures/invidious_playlist.json");
+
+    #[test]
+    fn retrieve_latest_items_reads_the_playlist() {
+        let server = MockServer::respond_with(200, PLAYLIST);
+        let items = InvidiousSource::new(&format!("{}/", server.url)).retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 10).unwrap();
+
+        assert_eq!(server.requests()[0].url, "/api/v1/playlists/UUaaaaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(items.iter().map(|item| item.id.as_str()).collect::<Vec<_>>(), ["h7Ur5tYeW3q", "i6Yt4rEwQ2p", "j5Tr3eWqA1o", "a5xvRqg9yDc"]);
+        assert!(items.iter().all(|item| item.playlist_id == "UUaaaaaaaaaaaaaaaaaaaaaa" && item.channel == "Wood Works"));
+
+        let videos = items.into_iter().map(|item| PlaylistItem::new(item, String::new())).collect::<Vec<_>>();
+        let kinds = videos.iter().map(|video| (video.kind, video.release_timestamp)).collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            (VideoKind::Live, None),
+            (VideoKind::Premiere, Some(1792300000)),
+            (VideoKind::Upcoming, Some(1792900000)),
+            (VideoKind::Upload, None),
+        ]);
+        assert_eq!((videos[0].duration, videos[3].duration), (0.0, 754.0));
+    }
+
+    #[test]
+    fn retrieve_latest_items_takes_only_the_amount() {
+        let server = MockServer::respond_with(200, PLAYLIST);
+
+        assert_eq!(InvidiousSource::new(&server.url).retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn retrieve_latest_items_fails_on_errors_of_the_instance() {
+        let server = MockServer::respond_with(500, "");
+        assert!(matches!(InvidiousSource::new(&server.url).retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 10), Err(VideoSourceError::RequestFailed(_))));
+
+        let server = MockServer::respond_with(200, "<html>maintenance</html>");
+        assert!(matches!(InvidiousSource::new(&server.url).retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 10), Err(VideoSourceError::ResponseParseFailed(_))));
+    }
+}
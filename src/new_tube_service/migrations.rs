@@ -0,0 +1,279 @@
+use rusqlite::{Connection, TransactionBehavior};
+
+/// A single step to bring the database schema to the next version
+pub struct Migration {
+    /// What the migration does
+    pub description: &'static str,
+    /// The SQL statements of the migration
+    pub sql: &'static str,
+}
+
+/// All migrations in the order they must be applied.
+///
+/// The schema version of the database (stored as the user_version pragma) is the amount of
+/// migrations that were already applied. Never change or remove a migration once it was released,
+/// always add a new one at the end instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Create the PlaylistItems table",
+        // The IF NOT EXISTS is required, as databases created by 0.6.0 already have this table
+        sql: "\
+        CREATE TABLE IF NOT EXISTS PlaylistItems (
+            playlist_id TEXT PRIMARY KEY,
+            video_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            duration REAL NOT NULL,
+            uploader TEXT NOT NULL,
+            previous_video_id NULL
+        );",
+    },
+    Migration {
+        description: "Create the Videos history table",
+        sql: "\
+        CREATE TABLE Videos (
+            video_id TEXT NOT NULL,
+            playlist_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            uploader TEXT NOT NULL,
+            duration REAL NOT NULL,
+            first_seen INTEGER NOT NULL,
+            notified INTEGER NOT NULL,
+            PRIMARY KEY (playlist_id, video_id)
+        );",
+    },
+    Migration {
+        description: "Give PlaylistItems.previous_video_id the type TEXT",
+        sql: "\
+        CREATE TABLE PlaylistItems_new (
+            playlist_id TEXT PRIMARY KEY,
+            video_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            duration REAL NOT NULL,
+            uploader TEXT NOT NULL,
+            previous_video_id TEXT NOT NULL
+        );
+        INSERT INTO PlaylistItems_new (playlist_id, video_id, title, duration, uploader, previous_video_id)
+            SELECT playlist_id, video_id, title, duration, uploader, COALESCE(previous_video_id, '') FROM PlaylistItems;
+        DROP TABLE PlaylistItems;
+        ALTER TABLE PlaylistItems_new RENAME TO PlaylistItems;",
+    },
+    Migration {
+        description: "Create the Users and Subscriptions tables",
+        sql: "\
+        CREATE TABLE Users (
+            user_id INTEGER PRIMARY KEY,
+            username TEXT NOT NULL,
+            chat_id INTEGER NOT NULL,
+            approved INTEGER NOT NULL,
+            admin INTEGER NOT NULL
+        );
+        CREATE TABLE Subscriptions (
+            user_id INTEGER NOT NULL,
+            playlist_id TEXT NOT NULL,
+            PRIMARY KEY (user_id, playlist_id)
+        );
+        ALTER TABLE PlaylistItems ADD COLUMN added_by_bot INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        description: "Track the health of every playlist",
+        sql: "\
+        ALTER TABLE PlaylistItems ADD COLUMN last_success INTEGER;
+        ALTER TABLE PlaylistItems ADD COLUMN last_error TEXT;
+        ALTER TABLE PlaylistItems ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE PlaylistItems ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1;",
+    },
+    Migration {
+        description: "Track livestreams and premieres",
+        sql: "\
+        ALTER TABLE PlaylistItems ADD COLUMN kind TEXT NOT NULL DEFAULT 'upload';
+        ALTER TABLE PlaylistItems ADD COLUMN release_timestamp INTEGER;
+        ALTER TABLE Videos ADD COLUMN kind TEXT NOT NULL DEFAULT 'upload';
+        ALTER TABLE Videos ADD COLUMN release_timestamp INTEGER;
+        CREATE TABLE KindNotifications (
+            user_id INTEGER NOT NULL,
+            playlist_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            notify INTEGER NOT NULL,
+            PRIMARY KEY (user_id, playlist_id, kind)
+        );",
+    },
+    Migration {
+        description: "Mark YouTube shorts",
+        sql: "\
+        ALTER TABLE PlaylistItems ADD COLUMN short INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE Videos ADD COLUMN short INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        description: "Create the Filters table",
+        // the filters for everyone belong to user 0
+        sql: "\
+        CREATE TABLE Filters (
+            filter_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL DEFAULT 0,
+            playlist_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            value TEXT NOT NULL
+        );",
+    },
+    Migration {
+        description: "Create the Downloads table",
+        sql: "\
+        CREATE TABLE Downloads (
+            video_id TEXT PRIMARY KEY,
+            playlist_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            file_path TEXT,
+            error TEXT,
+            updated INTEGER NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0
+        );",
+    },
+    Migration {
+        description: "Add the link styles to the Users table",
+        sql: "ALTER TABLE Users ADD COLUMN links TEXT;",
+    },
+    Migration {
+        description: "Create the FeedCache table",
+        sql: "\
+        CREATE TABLE FeedCache (
+            playlist_id TEXT PRIMARY KEY,
+            etag TEXT,
+            last_modified TEXT,
+            items TEXT NOT NULL
+        );",
+    },
+];
+
+/// The current schema version of the database and the migrations not applied yet
+pub struct MigrationStatus {
+    pub current_version: usize,
+    pub pending: Vec<&'static Migration>,
+}
+
+impl MigrationStatus {
+    /// The version the database will have once all migrations are applied
+    pub fn latest_version(&self) -> usize {
+        MIGRATIONS.len()
+    }
+}
+
+/// Apply all pending migrations in a single transaction.
+/// If any of them fails, the database stays untouched.
+pub fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    // the download workers open the database at the same time, so nothing is written if it is up to date
+    if schema_version(connection)? == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    // an immediate transaction waits for other writers, a deferred one fails when it starts to write
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let current_version = schema_version(&transaction)?;
+
+    for migration in MIGRATIONS.iter().skip(current_version) {
+        transaction.execute_batch(migration.sql)?;
+    }
+
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    transaction.commit()
+}
+
+/// Get the schema version of the database without changing anything
+pub fn status(connection: &Connection) -> rusqlite::Result<MigrationStatus> {
+    let current_version = schema_version(connection)?;
+
+    Ok(MigrationStatus {
+        current_version,
+        pending: MIGRATIONS.iter().skip(current_version).collect(),
+    })
+}
+
+fn schema_version(connection: &Connection) -> rusqlite::Result<usize> {
+    connection.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATABASE_0_6_0: &str = include_str!("../../tests/fixtures/new_tube_0.6.0.sql");
+
+    fn database_0_6_0() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(DATABASE_0_6_0).unwrap();
+        connection
+    }
+
+    #[test]
+    fn status_of_a_0_6_0_database_lists_every_migration() {
+        let connection = database_0_6_0();
+        let status = status(&connection).unwrap();
+
+        assert_eq!(status.current_version, 0);
+        assert_eq!(status.pending.len(), MIGRATIONS.len());
+        assert_eq!(status.latest_version(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrate_upgrades_a_0_6_0_database_and_keeps_its_playlists() {
+        let mut connection = database_0_6_0();
+        migrate(&mut connection).unwrap();
+
+        let status = status(&connection).unwrap();
+        assert_eq!(status.current_version, MIGRATIONS.len());
+        assert!(status.pending.is_empty());
+
+        let mut statement = connection.prepare("\
+            SELECT playlist_id, video_id, duration, previous_video_id, enabled, kind, short, added_by_bot FROM PlaylistItems
+            ORDER BY playlist_id;
+        ").unwrap();
+        let rows = statement
+            .query_map([], |row| Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, bool>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, bool>(6)?,
+                row.get::<_, bool>(7)?,
+            )))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(rows, [
+            ("UUaaaaaaaaaaaaaaaaaaaaaa".to_string(), "a5xvRqg9yDc".to_string(), 1312.0, "b4PLqW0XkTs".to_string(), true, "upload".to_string(), false, false),
+            // the untyped NULL of 0.6.0 became an empty text
+            ("UUbbbbbbbbbbbbbbbbbbbbbb".to_string(), "f9Wq2eRtYu0".to_string(), 0.0, String::new(), true, "upload".to_string(), false, false),
+        ]);
+    }
+
+    fn schema(connection: &Connection) -> Vec<String> {
+        let mut statement = connection.prepare("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY name").unwrap();
+        let rows = statement.query_map([], |row| row.get(0)).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn a_migrated_0_6_0_database_has_the_schema_of_a_new_one() {
+        let mut migrated = database_0_6_0();
+        migrate(&mut migrated).unwrap();
+        let mut new = Connection::open_in_memory().unwrap();
+        migrate(&mut new).unwrap();
+
+        assert_eq!(schema(&migrated), schema(&new));
+    }
+
+    #[test]
+    fn migrate_does_nothing_if_the_database_is_up_to_date() {
+        let mut connection = database_0_6_0();
+        migrate(&mut connection).unwrap();
+        connection.execute("INSERT INTO Filters (playlist_id, kind, value) VALUES ('UUaaaaaaaaaaaaaaaaaaaaaa', 'uploader', 'Wood Works')", []).unwrap();
+
+        migrate(&mut connection).unwrap();
+
+        let filters: i64 = connection.query_row("SELECT COUNT(*) FROM Filters", [], |row| row.get(0)).unwrap();
+        assert_eq!(filters, 1);
+        assert!(status(&connection).unwrap().pending.is_empty());
+    }
+}
@@ -1,104 +1,702 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
 use error_generator::error;
 
+use crate::config::{Config, ShortsFilter};
+use crate::feed::FeedItem;
+use crate::filter::{passes_rules, Filter, FilterRule};
+use crate::link::LinkStyle;
 use crate::new_tube_service::database::{DBError, Database};
-use crate::new_tube_service::yt_dlp::{retrieve_latest_items, Error, YTDLPResponse};
-use crate::playlist_item::PlaylistItem;
+use crate::new_tube_service::hook::{HookFailure, HookRunner};
+use crate::new_tube_service::playlist_id::{resolve_playlist_id, ResolveError};
+use crate::new_tube_service::throttle::{SystemClock, Throttle};
+use crate::new_tube_service::video_source::{create_video_source, VideoSource, VideoSourceError};
+use crate::new_tube_service::yt_dlp::{Error, YTDLPItem, YTDLPResponse};
+use crate::playlist_item::{PlaylistItem, VideoKind};
+use crate::playlist_status::PlaylistStatus;
+use crate::user::User;
 
 pub mod database;
+pub mod downloader;
+#[cfg(test)]
+pub mod fixture_source;
+pub mod hook;
+pub mod invidious;
+pub mod migrations;
+pub mod piped;
+pub mod playlist_id;
+pub mod process;
+pub mod throttle;
+pub mod video_source;
+pub mod youtube_feed;
 pub mod yt_dlp;
+#[cfg(test)]
+mod tests;
 
 pub type Result<T> = std::result::Result<T, NewTubeServiceError>;
 
+/// The service is shared by the threads of the bot and the server. Only the database needs a lock,
+/// so fetching does not block anything else.
 pub struct NewTubeService {
-    database: Database,
+    database: Mutex<Database>,
+    /// Held while the playlists are fetched, so no video is reported by two fetches running at the same time
+    fetching: Mutex<()>,
+    video_source: Box<dyn VideoSource>,
+    /// How many of the latest items of a playlist are fetched to find every new upload
+    fetch_depth: usize,
+    /// How many playlists are fetched at the same time
+    max_parallel_fetches: usize,
+    /// After how many failed fetches in a row a playlist gets disabled. 0 means never
+    max_consecutive_failures: u32,
+    /// What to do with new shorts, if the playlist has no filter of its own
+    shorts: ShortsFilter,
+    /// What to do with new shorts of specific playlists
+    playlist_shorts: HashMap<String, ShortsFilter>,
+    /// Runs the commands from the config for every new video
+    hooks: HookRunner,
+    /// Where the links to videos point to, unless a user chose other styles
+    links: Vec<LinkStyle>,
+    /// How long yt-dlp might take to look up a channel
+    yt_dlp_timeout: Duration,
+    /// Shared with the video source, so looking up channels counts towards the same limits as fetching
+    throttle: Arc<Throttle>,
 }
 
 impl NewTubeService {
-    pub fn new() -> Result<Self> {
-        Ok(NewTubeService {
-            database: Database::open()?
-        })
-    }
-
-    pub fn add_playlist(&self, id: &str) -> Result<()> {
-        let response = retrieve_latest_items(id)?;
-        let latest = response.latest_item;
-        let previous = response.previous_item;
-
-        let item = PlaylistItem {
-            playlist_id: latest.playlist_id,
-            video_id: latest.id,
-            title: latest.title,
-            duration: latest.duration.unwrap_or_default(),
-            uploader: latest.channel,
-            previous_video_id: previous.id,
-        };
+    /// Create the service with the default database and the video source from the config
+    pub fn new(config: &Config) -> Result<Self> {
+        let throttle = Arc::new(Throttle::new(config.throttle.clone(), SystemClock));
+        let video_source = create_video_source(&config.video_source, &throttle, Duration::from_secs(config.yt_dlp_timeout))?;
+        Ok(Self::with_video_source(config, Database::open()?, video_source, throttle))
+    }
 
-        self.database.add_item(&item)?;
-        Ok(())
+    pub fn with_video_source(config: &Config, database: Database, video_source: Box<dyn VideoSource>, throttle: Arc<Throttle>) -> Self {
+        NewTubeService {
+            database: Mutex::new(database),
+            fetching: Mutex::new(()),
+            video_source,
+            // at least the latest and the previous item are required to detect changes
+            fetch_depth: config.fetch_depth.max(2),
+            max_parallel_fetches: config.max_parallel_fetches.max(1),
+            max_consecutive_failures: config.max_consecutive_failures,
+            shorts: config.shorts,
+            playlist_shorts: config.playlist_shorts.clone(),
+            hooks: HookRunner::new(config.hooks.clone(), config.links.clone()),
+            links: config.links.clone(),
+            yt_dlp_timeout: Duration::from_secs(config.yt_dlp_timeout),
+            throttle,
+        }
+    }
+
+    /// Find the playlist id of the input, see resolve_playlist_id
+    fn resolve(&self, input: &str) -> Result<String> {
+        Ok(resolve_playlist_id(input, self.yt_dlp_timeout, &self.throttle)?)
+    }
+
+    fn database(&self) -> MutexGuard<'_, Database> {
+        self.database.lock().expect("the database should not be poisoned")
     }
 
-    pub fn get_new_videos_and_update_database(&self) -> Result<Vec<PlaylistItem>> {
-        let last_items = self.database.query_all_items()?;
-        let mut new_videos = vec![];
+    /// Add the playlist specified by the input, which can be anything resolve_playlist_id accepts.
+    /// Playlists without videos can be added too, their first video is a new one.
+    /// The playlist belongs to the admin, even if it was added with the bot before.
+    /// Returns the id of the added playlist.
+    pub fn add_playlist(&self, input: &str) -> Result<String> {
+        let id = self.store_playlist(input)?;
+        self.database().set_added_by_bot(&id, false)?;
+        Ok(id)
+    }
 
-        for last in last_items {
-            let new = self.get_new_video(&last)?;
+    fn store_playlist(&self, input: &str) -> Result<String> {
+        let id = self.resolve(input)?;
+        let items = self.video_source.retrieve_latest_items(&id, 2)?;
+
+        match YTDLPResponse::from_items(items) {
+            Some(response) => {
+                let previous_video_id = response.previous_item.map(|item| item.id).unwrap_or_default();
+                let item = PlaylistItem::new(response.latest_item, previous_video_id);
+                self.database().add_item(&item)?;
+                self.database().add_to_history(&item, false)?;
+            }
+            None => self.database().add_item(&PlaylistItem::empty(&id))?
+        }
+
+        Ok(id)
+    }
+
+    /// Fetch every playlist and store the changes in the database.
+    ///
+    /// A playlist which cannot be fetched does not stop the others from being checked. Its error
+    /// is collected in the result instead. Only database errors abort the whole cycle.
+    /// Playlists which failed too often in a row get disabled and are no longer fetched.
+    ///
+    /// Besides new videos, known videos which changed their kind (like an upcoming livestream which
+    /// is live now) are returned too. Videos of kinds the user muted for a playlist are returned separately, nobody is notified about them.
+    ///
+    /// The hooks from the config are run for every new video, including the muted ones. They run in the background
+    /// once the result is complete, so their failures are part of the result of the next fetch.
+    pub fn get_new_videos_and_update_database(&self) -> Result<FetchResult> {
+        let _fetching = self.fetching.lock().expect("the fetch lock should not be poisoned");
+        let last_items = self.database().query_enabled_items()?;
+        let mut result = FetchResult {
+            hook_failures: self.hooks.take_failures(),
+            ..FetchResult::default()
+        };
+        let mut hook_jobs = vec![];
+
+        // The fetches run in parallel, but the database is only updated from this thread,
+        // in the order of the playlists in the database
+        for (last, fetched) in last_items.iter().zip(self.fetch_in_parallel(&last_items)) {
+            let (new, items) = match fetched.map(|items| (Self::get_new_video(&items, last), items)) {
+                Ok(result) => result,
+                // being rate limited is not the fault of the playlist, so this is no failure
+                Err(NewTubeServiceError::VideoSourceError(VideoSourceError::Paused(_))) => {
+                    result.skipped.push(last.playlist_id.clone());
+                    continue;
+                }
+                Err(error) => {
+                    // the playlist was deleted while it was fetched
+                    let Some(consecutive_failures) = self.database().record_fetch_failure(&last.playlist_id, &error.to_string())? else {
+                        continue;
+                    };
+
+                    if self.max_consecutive_failures > 0 && consecutive_failures >= self.max_consecutive_failures {
+                        self.database().set_enabled(&last.playlist_id, false)?;
+                        result.disabled.push(last.playlist_id.clone());
+                    }
+
+                    result.failures.push(FetchFailure {
+                        playlist_id: last.playlist_id.clone(),
+                        error,
+                    });
+                    continue;
+                }
+            };
+
+            // the playlist was deleted while it was fetched, so its results are dropped
+            if !self.database().record_fetch_success(&last.playlist_id)? {
+                continue;
+            }
+            let muted_kinds = self.database().get_muted_kinds(None, &last.playlist_id)?;
+            // the filters of single users are applied when their chats are notified
+            let rules = self.database()
+                .get_filters(Some(&last.playlist_id))?
+                .into_iter()
+                .filter(|filter| filter.user_id.is_none())
+                .map(|filter| filter.rule)
+                .collect::<Vec<_>>();
 
             match new {
-                // A new video which must be saved and returned to the user
-                NewVideo::ReallyNew(video) => {
-                    self.database.add_item(&video)?;
-                    new_videos.push(video);
+                // New videos which must be saved and returned to the user.
+                // Only the latest one is stored as the current state of the playlist
+                NewVideo::ReallyNew(videos) => {
+                    if let Some(latest) = videos.last() {
+                        self.database().update_item(latest)?;
+                    }
+                    for video in videos {
+                        let target = self.get_notification_target(&video, &muted_kinds, &rules);
+                        let notified = target != NotificationTarget::Nobody;
+                        self.database().add_to_history(&video, notified)?;
+                        hook_jobs.push((video.clone(), notified));
+                        result.add(video, target);
+                    }
                 }
                 // A video which replaces a now removed one.
                 // This needs only to be saved
                 NewVideo::OldVideoNowLatest(video) => {
-                    self.database.add_item(&video)?;
+                    self.database().update_item(&video)?;
+                    self.database().add_to_history(&video, false)?;
                 }
                 // Nothing new, so nothing to do here
                 NewVideo::SameAsBefore => {}
             }
+
+            for video in self.get_kind_changes(&items)? {
+                self.database().update_history_kind(&video)?;
+
+                // the stored latest item must reflect the change too
+                if video.video_id == items[0].id {
+                    self.database().update_item(&video)?;
+                }
+
+                let target = self.get_notification_target(&video, &muted_kinds, &rules);
+                result.add(video, target);
+            }
+        }
+
+        for (video, notified) in hook_jobs {
+            self.hooks.enqueue(video, notified);
+        }
+
+        Ok(result)
+    }
+
+    /// Decide who should be notified about the given new video. Nobody is notified about videos
+    /// which do not pass the filter rules of their playlist.
+    fn get_notification_target(&self, video: &PlaylistItem, muted_kinds: &[VideoKind], rules: &[FilterRule]) -> NotificationTarget {
+        if muted_kinds.contains(&video.kind) || !passes_rules(video, rules) {
+            return NotificationTarget::Nobody;
         }
 
-        Ok(new_videos)
+        if !video.short {
+            return NotificationTarget::Subscribers;
+        }
 
+        match self.playlist_shorts.get(&video.playlist_id).copied().unwrap_or(self.shorts) {
+            ShortsFilter::Include => NotificationTarget::Subscribers,
+            ShortsFilter::Exclude => NotificationTarget::Nobody,
+            ShortsFilter::SeparateChat(chat_id) => NotificationTarget::Chat(chat_id)
+        }
     }
 
-    fn get_new_video(&self, last: &PlaylistItem) -> Result<NewVideo> {
-        let YTDLPResponse {latest_item, previous_item} = retrieve_latest_items(&last.playlist_id)?;
-        let latest = PlaylistItem::new(latest_item, previous_item.id.clone());
+    /// Fetch the latest fetch_depth items of every given playlist on a pool of at most
+    /// max_parallel_fetches threads. The results have the same order as the playlists.
+    fn fetch_in_parallel(&self, last_items: &[PlaylistItem]) -> Vec<Result<Vec<YTDLPItem>>> {
+        let video_source = self.video_source.as_ref();
+        let fetch_depth = self.fetch_depth;
+        let next_index = AtomicUsize::new(0);
+        let results = Mutex::new(last_items.iter().map(|_| None).collect::<Vec<_>>());
 
-        if latest.video_id == last.video_id {
+        thread::scope(|scope| {
+            for _ in 0..self.max_parallel_fetches.min(last_items.len()) {
+                scope.spawn(|| loop {
+                    // every worker takes the next item nobody fetched yet
+                    let index = next_index.fetch_add(1, Ordering::Relaxed);
+
+                    let Some(last) = last_items.get(index) else {
+                        break;
+                    };
+
+                    let items = video_source
+                        .retrieve_latest_items(&last.playlist_id, fetch_depth)
+                        .map_err(NewTubeServiceError::from);
+                    results.lock().expect("the results should not be poisoned")[index] = Some(items);
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .expect("the results should not be poisoned")
+            .into_iter()
+            .map(|items| items.expect("every playlist should have been fetched"))
+            .collect()
+    }
+
+    /// Compare the latest items of the playlist with its last known state.
+    ///
+    /// Up to fetch_depth items are retrieved, so every upload since the last check can be found,
+    /// as long as the last known video (the anchor) is still part of the fetched items.
+    ///
+    /// A playlist might have less than two videos, so neither the previous video of the last known one
+    /// nor the previous video of a fetched one must exist.
+    fn get_new_video(items: &[YTDLPItem], last: &PlaylistItem) -> NewVideo {
+        let position_of = |video_id: &str| items.iter().position(|item| !video_id.is_empty() && item.id == video_id);
+
+        if items.is_empty() {
+            // Either every video was removed or the video source returned nothing by mistake.
+            // The last known state is kept, so nothing is reported twice if the videos show up again
+            NewVideo::SameAsBefore
+        } else if last.is_empty() {
+            // The playlist had no videos before. Only the latest video is considered new, as the video source
+            // might have returned nothing by mistake when the playlist was added, so the others might be old
+            NewVideo::ReallyNew(Self::to_playlist_items(items, 1))
+        } else if items[0].id == last.video_id {
             // The latest video did not change, so no new video here
-            Ok(NewVideo::SameAsBefore)
-        } else if latest.video_id == last.previous_video_id {
+            NewVideo::SameAsBefore
+        } else if let Some(position) = position_of(&last.video_id) {
+            // Every video uploaded after the last known one is new
+            NewVideo::ReallyNew(Self::to_playlist_items(items, position))
+        } else if items[0].id == last.previous_video_id {
             // The latest video of the playlist is now the previous latest from the database.
             // This means the current latest video stored in the database was removed from the
             // playlist for any reason. In this case, just return what yt_dlp currently returned as
             // the latest one, as this will overwrite the now invalid entry in the db.
-            Ok(NewVideo::OldVideoNowLatest(latest))
+            NewVideo::OldVideoNowLatest(Self::to_playlist_items(items, 1).remove(0))
+        } else if let Some(position) = position_of(&last.previous_video_id) {
+            // The last known video was removed, but new ones were uploaded after the previous one.
+            NewVideo::ReallyNew(Self::to_playlist_items(items, position))
         } else {
-            // The video is not the same as the current one and also not a previous one.
-            // It is really new
-            Ok(NewVideo::ReallyNew(latest))
+            // Neither the last known nor the previous video could be found, so it is unknown how
+            // many of the fetched videos are really new. Only the latest one is considered new,
+            // as the user should not be flooded with possibly old videos.
+            NewVideo::ReallyNew(Self::to_playlist_items(items, 1))
         }
     }
 
-    pub fn replace(&self, old_id: &str, new_id: &str) -> Result<()> {
-        self.delete(old_id)?;
-        self.add_playlist(new_id)
+    /// Find the fetched videos which are already known, but changed their kind since they were last seen,
+    /// like an upcoming livestream which is live now.
+    fn get_kind_changes(&self, items: &[YTDLPItem]) -> Result<Vec<PlaylistItem>> {
+        let mut changes = vec![];
+
+        for (index, item) in items.iter().enumerate() {
+            let previous_video_id = items.get(index + 1).map(|previous| previous.id.clone()).unwrap_or_default();
+            let mut video = PlaylistItem::new(item.clone(), previous_video_id);
+
+            if let Some(kind) = self.database().get_history_kind(&video.playlist_id, &video.video_id)? {
+                video.kind = Self::reported_kind(kind, video.kind);
+
+                if kind != video.kind {
+                    changes.push(video);
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// The kind of a known video, given the kind it had and the one the source reports now.
+    /// Not every source can tell finished livestreams from uploads, so an upload is only a change if it could not
+    /// have been a livestream, which keeps the kinds the same when the source is switched.
+    fn reported_kind(known: VideoKind, reported: VideoKind) -> VideoKind {
+        match (known, reported) {
+            (VideoKind::Live | VideoKind::Upcoming, VideoKind::Upload) => VideoKind::PastLive,
+            (VideoKind::PastLive, VideoKind::Upload) | (VideoKind::Upload, VideoKind::PastLive) => known,
+            _ => reported
+        }
+    }
+
+    /// Convert the first 'amount' items (newest first) to playlist items in upload order.
+    /// The last of the items has no previous one, unless more items were fetched.
+    fn to_playlist_items(items: &[YTDLPItem], amount: usize) -> Vec<PlaylistItem> {
+        (0..amount)
+            .rev()
+            .map(|i| PlaylistItem::new(items[i].clone(), items.get(i + 1).map(|previous| previous.id.clone()).unwrap_or_default()))
+            .collect()
+    }
+
+    /// Wait until the hooks ran for all new videos and get the ones which failed since the last fetch
+    pub fn finish_hooks(&self) -> Vec<HookFailure> {
+        self.hooks.finish();
+        self.hooks.take_failures()
+    }
+
+    /// Get the ids of all playlists new_tube knows
+    pub fn get_playlist_ids(&self) -> Result<Vec<String>> {
+        Ok(self.database().get_playlist_ids()?)
+    }
+
+    /// Get the videos from the history, newest first. See Database::query_history.
+    /// The date must look like 2026-10-18, as SQLite ignores invalid dates instead of rejecting them.
+    pub fn get_history(&self, channel: Option<&str>, since: Option<&str>) -> Result<Vec<PlaylistItem>> {
+        if let Some(since) = since {
+            Self::check_date(since)?;
+        }
+
+        Ok(self.database().query_history(channel, since)?)
+    }
+
+    fn check_date(text: &str) -> Result<()> {
+        let invalid = || NewTubeServiceError::InvalidDate(text.to_string());
+        let parts = text.split('-').collect::<Vec<_>>();
+
+        let [year, month, day] = parts[..] else {
+            return Err(invalid());
+        };
+
+        let number = |part: &str, length: usize| match part.len() == length && part.chars().all(|char| char.is_ascii_digit()) {
+            true => part.parse::<u32>().map_err(|_| invalid()),
+            false => Err(invalid())
+        };
+        let (year, month, day) = (number(year, 4)?, number(month, 2)?, number(day, 2)?);
+        let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days_in_month = match month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return Err(invalid())
+        };
+
+        match (1..=days_in_month).contains(&day) {
+            true => Ok(()),
+            false => Err(invalid())
+        }
+    }
+
+    /// Get the latest finished videos of the given playlists for a feed, newest first
+    pub fn get_feed_items(&self, playlist_ids: &[String], limit: usize) -> Result<Vec<FeedItem>> {
+        let mut items = vec![];
+
+        for playlist_id in playlist_ids {
+            items.extend(self.database().query_feed_items(playlist_id, limit)?);
+        }
+
+        items.sort_by_key(|item| Reverse(item.first_seen));
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    /// Get the last known video of every playlist which has any videos
+    pub fn get_last_items(&self) -> Result<Vec<PlaylistItem>> {
+        Ok(self.database()
+            .query_all_items()?
+            .into_iter()
+            .filter(|item| !item.is_empty())
+            .collect())
+    }
+
+    pub fn replace(&self, old_input: &str, new_input: &str) -> Result<()> {
+        self.delete(&self.resolve(old_input)?)?;
+        self.add_playlist(new_input)?;
+        Ok(())
     }
 
     pub fn delete(&self, id: &str) -> Result<()> {
-        Ok(self.database.delete(id)?)
+        Ok(self.database().delete(id)?)
     }
+
+    /// Enable or disable fetching the given playlist
+    pub fn set_enabled(&self, id: &str, enabled: bool) -> Result<()> {
+        if self.database().set_enabled(id, enabled)? {
+            Ok(())
+        } else {
+            Err(NewTubeServiceError::UnknownPlaylist(id.to_string()))
+        }
+    }
+
+    /// Set if the user wants to be notified about videos of the given kind from the playlist.
+    /// Without a user, the setting applies to everyone, including the default chat and the notifiers.
+    pub fn set_kind_notification(&self, user_id: Option<i64>, input: &str, kind: VideoKind, notify: bool) -> Result<()> {
+        let playlist_id = self.resolve(input)?;
+
+        if !self.database().get_playlist_ids()?.contains(&playlist_id) {
+            return Err(NewTubeServiceError::UnknownPlaylist(playlist_id));
+        }
+
+        Ok(self.database().set_kind_notification(user_id, &playlist_id, kind, notify)?)
+    }
+
+    /// Add a filter rule of the user to the playlist. Without a user, the rule applies to everyone.
+    /// Returns the id of the new filter.
+    pub fn add_filter(&self, user_id: Option<i64>, input: &str, kind: &str, value: &str) -> Result<i64> {
+        let playlist_id = self.resolve(input)?;
+
+        if !self.database().get_playlist_ids()?.contains(&playlist_id) {
+            return Err(NewTubeServiceError::UnknownPlaylist(playlist_id));
+        }
+
+        let rule = FilterRule::parse(kind, value).map_err(NewTubeServiceError::InvalidFilter)?;
+        Ok(self.database().add_filter(user_id, &playlist_id, &rule)?)
+    }
+
+    /// Remove the filter, if the user is allowed to see it. Without a user, any filter can be removed.
+    pub fn remove_filter(&self, user: Option<&User>, filter_id: i64) -> Result<()> {
+        let visible = self.database()
+            .get_filters(None)?
+            .iter()
+            .any(|filter| filter.id == filter_id && Self::can_see_filter(user, filter));
+
+        if visible && self.database().delete_filter(filter_id)? {
+            Ok(())
+        } else {
+            Err(NewTubeServiceError::UnknownFilter(filter_id))
+        }
+    }
+
+    /// Get the filters the user is allowed to see for the given playlist, or for all playlists if no playlist is given.
+    /// Without a user, all filters are returned.
+    pub fn get_filters(&self, user: Option<&User>, input: Option<&str>) -> Result<Vec<Filter>> {
+        let playlist_id = input.map(|input| self.resolve(input)).transpose()?;
+
+        Ok(self.database()
+            .get_filters(playlist_id.as_deref())?
+            .into_iter()
+            .filter(|filter| Self::can_see_filter(user, filter))
+            .collect())
+    }
+
+    /// Admins can see all filters, other users only their own ones
+    fn can_see_filter(user: Option<&User>, filter: &Filter) -> bool {
+        match user {
+            Some(user) => user.admin || filter.user_id == Some(user.id),
+            None => true
+        }
+    }
+
+    pub fn get_statuses(&self) -> Result<Vec<PlaylistStatus>> {
+        Ok(self.database().query_statuses()?)
+    }
+
+    /// Subscribe the user to the playlist. The playlist is added if new_tube does not know it yet,
+    /// it is then deleted again once nobody is subscribed to it anymore.
+    /// Returns the id of the playlist.
+    pub fn subscribe(&self, user_id: i64, input: &str) -> Result<String> {
+        let playlist_id = self.resolve(input)?;
+
+        if !self.database().get_playlist_ids()?.contains(&playlist_id) {
+            self.store_playlist(&playlist_id)?;
+            self.database().set_added_by_bot(&playlist_id, true)?;
+        }
+
+        self.database().add_subscription(user_id, &playlist_id)?;
+        Ok(playlist_id)
+    }
+
+    /// Remove the subscription of the user. If the playlist was added with the bot and this was its last
+    /// subscription, it is deleted, so it is no longer fetched. Playlists of the admin are never deleted.
+    pub fn unsubscribe(&self, user_id: i64, input: &str) -> Result<()> {
+        let playlist_id = self.resolve(input)?;
+
+        if !self.database().delete_subscription(user_id, &playlist_id)? {
+            return Err(NewTubeServiceError::NotSubscribed(playlist_id));
+        }
+
+        if self.is_abandoned(&playlist_id)? {
+            self.delete(&playlist_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace a subscription of the user with another one. If the user is not subscribed to the old playlist,
+    /// nothing changes. Returns the id of the new playlist.
+    pub fn replace_subscription(&self, user_id: i64, old_input: &str, new_input: &str) -> Result<String> {
+        let old_playlist_id = self.resolve(old_input)?;
+
+        if !self.database().get_subscriptions(user_id)?.contains(&old_playlist_id) {
+            return Err(NewTubeServiceError::NotSubscribed(old_playlist_id));
+        }
+
+        let new_playlist_id = self.subscribe(user_id, new_input)?;
+
+        if new_playlist_id != old_playlist_id {
+            self.unsubscribe(user_id, &old_playlist_id)?;
+        }
+
+        Ok(new_playlist_id)
+    }
+
+    /// Check if the playlist was added with the bot and nobody is subscribed to it anymore
+    fn is_abandoned(&self, playlist_id: &str) -> Result<bool> {
+        let database = self.database();
+        Ok(database.is_added_by_bot(playlist_id)? && !database.has_subscribers(playlist_id)?)
+    }
+
+    pub fn get_subscriptions(&self, user_id: i64) -> Result<Vec<String>> {
+        Ok(self.database().get_subscriptions(user_id)?)
+    }
+
+    /// Get the chats the new video must be sent to, with the link styles of their users.
+    /// Subscribers who muted the kind of the video for its playlist or whose filters it does not pass are skipped.
+    /// Playlists of the admin (like the ones added via the CLI) also go to the default chat, even if users subscribed to them.
+    pub fn get_chats_to_notify(&self, video: &PlaylistItem, default_chat_id: i64) -> Result<Vec<(i64, Vec<LinkStyle>)>> {
+        let mut chats = vec![];
+        let filters = self.database().get_filters(Some(&video.playlist_id))?;
+        let subscribers = self.database().get_subscribers(&video.playlist_id)?;
+
+        for user in subscribers {
+            let rules = filters
+                .iter()
+                .filter(|filter| filter.user_id == Some(user.id))
+                .map(|filter| filter.rule.clone())
+                .collect::<Vec<_>>();
+
+            if !self.database().get_muted_kinds(Some(user.id), &video.playlist_id)?.contains(&video.kind) && passes_rules(video, &rules) {
+                chats.push((user.chat_id, self.get_link_styles(Some(&user)).to_vec()))
+            }
+        }
+
+        if !self.database().is_added_by_bot(&video.playlist_id)? && chats.iter().all(|(chat_id, _)| *chat_id != default_chat_id) {
+            chats.push((default_chat_id, self.links.clone()));
+        }
+
+        Ok(chats)
+    }
+
+    /// The link styles the user chose, or the ones from the config if there is no user or the user chose none
+    pub fn get_link_styles<'a>(&'a self, user: Option<&'a User>) -> &'a [LinkStyle] {
+        user.and_then(|user| user.links.as_deref()).unwrap_or(&self.links)
+    }
+
+    pub fn get_user(&self, user_id: i64) -> Result<Option<User>> {
+        Ok(self.database().get_user(user_id)?)
+    }
+
+    pub fn get_user_by_name(&self, username: &str) -> Result<Option<User>> {
+        Ok(self.database().get_user_by_name(username)?)
+    }
+
+    pub fn get_users(&self) -> Result<Vec<User>> {
+        Ok(self.database().get_users()?)
+    }
+
+    /// Add the user or update it if it already exists
+    pub fn save_user(&self, user: &User) -> Result<()> {
+        Ok(self.database().add_user(user)?)
+    }
+
+    /// Delete the user and all of its subscriptions. Playlists added with the bot which nobody is subscribed to anymore are deleted too.
+    pub fn delete_user(&self, user_id: i64) -> Result<()> {
+        let subscriptions = self.database().get_subscriptions(user_id)?;
+        self.database().delete_user(user_id)?;
+
+        for playlist_id in subscriptions {
+            if self.is_abandoned(&playlist_id)? {
+                self.delete(&playlist_id)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The outcome of fetching all playlists
+#[derive(Default)]
+pub struct FetchResult {
+    /// All new videos and known videos which changed their kind, which should be broadcast to the user
+    pub new_videos: Vec<PlaylistItem>,
+    /// New videos which must be sent to a specific chat instead of the subscribers of their playlist
+    pub separate_chat_videos: Vec<(i64, PlaylistItem)>,
+    /// New videos nobody is notified about, as they were muted or filtered. They are still downloaded
+    pub muted_videos: Vec<PlaylistItem>,
+    /// The playlists which could not be fetched
+    pub failures: Vec<FetchFailure>,
+    /// The ids of the playlists which were disabled in this cycle, as they failed too often
+    pub disabled: Vec<String>,
+    /// The hooks which failed for the new videos of the previous fetch, as hooks run in the background
+    pub hook_failures: Vec<HookFailure>,
+    /// The ids of the playlists which were not fetched, as fetching was paused after too many rate limited requests
+    pub skipped: Vec<String>,
+}
+
+impl FetchResult {
+    fn add(&mut self, video: PlaylistItem, target: NotificationTarget) {
+        match target {
+            NotificationTarget::Subscribers => self.new_videos.push(video),
+            NotificationTarget::Chat(chat_id) => self.separate_chat_videos.push((chat_id, video)),
+            NotificationTarget::Nobody => self.muted_videos.push(video),
+        }
+    }
+}
+
+/// Who gets notified about a new video
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NotificationTarget {
+    /// Everyone subscribed to the playlist of the video
+    Subscribers,
+    /// Only the given chat
+    Chat(i64),
+    /// The video is only saved
+    Nobody,
+}
+
+/// A playlist which could not be fetched, and why
+pub struct FetchFailure {
+    pub playlist_id: String,
+    pub error: NewTubeServiceError,
 }
 
 /// The different states a "new video" returned by the service can be in
 pub enum NewVideo {
-    /// The video really is new and should be broadcast to the user
-    ReallyNew(PlaylistItem),
+    /// The videos really are new and should be broadcast to the user.
+    /// They are sorted in upload order, so the last one is the latest video of the playlist
+    ReallyNew(Vec<PlaylistItem>),
     /// A video was removed from a playlist, leaving this video as the new latest one.
     /// This should not be broadcast to the user, as they already know it
     OldVideoNowLatest(PlaylistItem),
@@ -106,10 +704,36 @@ pub enum NewVideo {
     SameAsBefore
 }
 
+impl NewTubeServiceError {
+    /// The error of yt-dlp which caused this error, if there is one
+    pub fn yt_dlp_error(&self) -> Option<&Error> {
+        match self {
+            NewTubeServiceError::YTDLPError(error)
+            | NewTubeServiceError::VideoSourceError(VideoSourceError::YTDLPError(error))
+            | NewTubeServiceError::ResolveError(ResolveError::VideoSourceError(VideoSourceError::YTDLPError(error))) => Some(error),
+            _ => None
+        }
+    }
+}
+
 #[error]
 pub enum NewTubeServiceError {
     #[error(message = "{_0}", impl_from)]
     DatabaseAccessFailed(DBError),
     #[error(message = "{_0}", impl_from)]
     YTDLPError(Error),
+    #[error(message = "{_0}", impl_from)]
+    VideoSourceError(VideoSourceError),
+    #[error(message = "{_0}", impl_from)]
+    ResolveError(ResolveError),
+    #[error(message = "The playlist {_0} does not exist")]
+    UnknownPlaylist(String),
+    #[error(message = "Invalid filter: {_0}")]
+    InvalidFilter(String),
+    #[error(message = "The filter {_0} does not exist")]
+    UnknownFilter(i64),
+    #[error(message = "You are not subscribed to the playlist {_0}")]
+    NotSubscribed(String),
+    #[error(message = "'{_0}' is no valid date, expected YYYY-MM-DD")]
+    InvalidDate(String),
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::new_tube_service::video_source::{http_agent, Result, VideoSource, VideoSourceError};
+use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+/// A video source which uses the api of a Piped instance (https://docs.piped.video/docs/api-documentation/).
+pub struct PipedSource {
+    agent: ureq::Agent,
+    /// The url of the api of the instance, like https://pipedapi.kavin.rocks
+    api_url: String,
+}
+
+impl PipedSource {
+    pub fn new(api_url: &str) -> Self {
+        PipedSource {
+            agent: http_agent(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl VideoSource for PipedSource {
+    // Example: GET https://pipedapi.kavin.rocks/playlists/<PLAYLIST_ID>
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>> {
+        let playlist: Playlist = self.agent
+            .get(&format!("{}/playlists/{playlist_id}", self.api_url))
+            .call()
+            .map_err(Box::new)?
+            .into_json()?;
+
+        playlist
+            .related_streams
+            .into_iter()
+            .take(amount)
+            .map(|stream| stream.into_item(playlist_id))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Playlist {
+    related_streams: Vec<Stream>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Stream {
+    /// The path of the video, like /watch?v=<VIDEO_ID>
+    url: String,
+    title: String,
+    uploader_name: String,
+    /// -1 for running and upcoming livestreams
+    duration: i64,
+    /// When the video was uploaded, as a unix timestamp in milliseconds. For upcoming videos, this is when they
+    /// start, as Piped has no flag for them. -1 if unknown
+    #[serde(default = "unknown_upload_time")]
+    uploaded: i64,
+    /// When the video was uploaded, as text. Finished livestreams are 'Streamed ... ago'
+    #[serde(default)]
+    uploaded_date: Option<String>,
+    #[serde(default)]
+    is_short: bool,
+}
+
+fn unknown_upload_time() -> i64 {
+    -1
+}
+
+impl Stream {
+    fn into_item(self, playlist_id: &str) -> Result<YTDLPItem> {
+        let id = self.url
+            .strip_prefix("/watch?v=")
+            .ok_or_else(|| VideoSourceError::UnexpectedResponse(format!("unknown video url {}", self.url)))?
+            .to_string();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis() as i64).unwrap_or_default();
+        let is_upcoming = self.uploaded > now;
+        let was_live = self.uploaded_date.as_deref().is_some_and(|date| date.starts_with("Streamed"));
+
+        Ok(YTDLPItem {
+            playlist_id: playlist_id.to_string(),
+            // the short detection of the playlist items only looks at the url
+            url: self.is_short.then(|| format!("https://www.youtube.com/shorts/{id}")),
+            id,
+            title: self.title,
+            duration: (self.duration > 0).then_some(self.duration as f32),
+            channel: self.uploader_name,
+            // the same statuses yt-dlp uses, so switching the source does not change the kinds of the videos
+            live_status: match (is_upcoming, self.duration < 0, was_live) {
+                (true, _, _) => Some("is_upcoming".to_string()),
+                (_, true, _) => Some("is_live".to_string()),
+                (_, _, true) => Some("was_live".to_string()),
+                _ => None
+            },
+            release_timestamp: is_upcoming.then_some(self.uploaded / 1000),
+            width: None,
+            height: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::playlist_item::{PlaylistItem, VideoKind};
+
+    const PLAYLIST: &str = include_str!("../../tests/fixtures/piped_playlist.json");
+
+    #[test]
+    fn retrieve_latest_items_reads_the_playlist() {
+        let server = MockServer::respond_with(200, PLAYLIST);
+        let items = PipedSource::new(&format!("{}/", server.url)).retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 10).unwrap();
+
+        assert_eq!(server.requests()[0].url, "/playlists/UUaaaaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(items.iter().map(|item| item.id.as_str()).collect::<Vec<_>>(), ["i6Yt4rEwQ2p", "j5Tr3eWqA1o", "h7Ur5tYeW3q", "l3Ew1qAzX9u", "k4Re2wQaS0i", "a5xvRqg9yDc"]);
+
+        let videos = items.into_iter().map(|item| PlaylistItem::new(item, String::new())).collect::<Vec<_>>();
+        let kinds = videos.iter().map(|video| (video.kind, video.release_timestamp)).collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            (VideoKind::Premiere, Some(4102444800)),
+            (VideoKind::Upcoming, Some(4103049600)),
+            (VideoKind::Live, None),
+            (VideoKind::PastLive, None),
+            (VideoKind::Upload, None),
+            (VideoKind::Upload, None),
+        ]);
+        assert_eq!(videos.iter().map(|video| video.short).collect::<Vec<_>>(), [false, false, false, false, true, false]);
+        assert_eq!(videos.iter().map(|video| video.duration).collect::<Vec<_>>(), [912.0, 0.0, 0.0, 3605.0, 58.0, 754.0]);
+    }
+
+    #[test]
+    fn retrieve_latest_items_rejects_unknown_video_urls() {
+        let server = MockServer::respond_with(200, r#"{"relatedStreams": [{"url": "/channel/UCaaaaaaaaaaaaaaaaaaaaaa", "title": "", "uploaderName": "", "duration": 1}]}"#);
+
+        assert!(matches!(PipedSource::new(&server.url).retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 10), Err(VideoSourceError::UnexpectedResponse(_))));
+    }
+}
@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use error_generator::error;
+
+use crate::new_tube_service::throttle::Throttle;
+use crate::new_tube_service::video_source::VideoSourceError;
+use crate::new_tube_service::yt_dlp;
+
+/// The different ways a user can specify which playlist to add
+#[derive(Debug, PartialEq)]
+pub enum PlaylistInput {
+    /// The id of a playlist, which can be used as is
+    PlaylistId(String),
+    /// The id of a channel (UC…). Its "All Videos" playlist has the same id, starting with UU instead
+    ChannelId(String),
+    /// The URL of a channel page, like the one of a @handle, which yt-dlp must resolve to a channel id
+    ChannelUrl(String),
+    /// The URL of a video, which yt-dlp must resolve to the id of the uploading channel
+    VideoUrl(String),
+}
+
+impl PlaylistInput {
+    /// Find out what kind of input the user provided, without any network access.
+    ///
+    /// Accepted are playlist ids, channel ids, @handles and YouTube URLs to playlists, channels and videos.
+    /// Returns None if the input is none of these, like a URL of another site.
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+
+        if let Some(handle) = input.strip_prefix('@').filter(|handle| is_id(handle)) {
+            return Some(PlaylistInput::ChannelUrl(format!("https://www.youtube.com/@{handle}/videos")));
+        }
+
+        if !input.contains('/') {
+            return match input {
+                _ if is_channel_id(input) => Some(PlaylistInput::ChannelId(input.to_string())),
+                _ if is_id(input) => Some(PlaylistInput::PlaylistId(input.to_string())),
+                _ => None
+            };
+        }
+
+        let without_scheme = input.split_once("://").map(|(_, rest)| rest).unwrap_or(input);
+        let (host, path_and_query) = without_scheme.split_once('/').unwrap_or((without_scheme, ""));
+        let host = host.trim_start_matches("www.").trim_start_matches("m.").trim_start_matches("music.");
+        let (path, query) = path_and_query.split_once('?').unwrap_or((path_and_query, ""));
+        let query_parameter = |name: &str| query
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix(name)?.strip_prefix('='))
+            .filter(|value| !value.is_empty());
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+
+        if !matches!(host, "youtube.com" | "youtu.be") {
+            return None;
+        }
+
+        if let Some(list) = query_parameter("list").filter(|list| is_id(list)) {
+            return Some(PlaylistInput::PlaylistId(list.to_string()));
+        }
+
+        match (host, segments.next(), segments.next()) {
+            ("youtu.be", Some(video_id), _) => Some(Self::video(video_id)),
+            ("youtube.com", Some("watch"), _) => query_parameter("v").map(Self::video),
+            ("youtube.com", Some("shorts" | "live" | "embed"), Some(video_id)) => Some(Self::video(video_id)),
+            ("youtube.com", Some("channel"), Some(channel_id)) if is_channel_id(channel_id) => Some(PlaylistInput::ChannelId(channel_id.to_string())),
+            ("youtube.com", Some(handle), _) if handle.starts_with('@') => Some(PlaylistInput::ChannelUrl(format!("https://www.youtube.com/{handle}/videos"))),
+            ("youtube.com", Some(kind @ ("c" | "user")), Some(name)) => Some(PlaylistInput::ChannelUrl(format!("https://www.youtube.com/{kind}/{name}/videos"))),
+            _ => None
+        }
+    }
+
+    fn video(video_id: &str) -> Self {
+        PlaylistInput::VideoUrl(format!("https://www.youtube.com/watch?v={video_id}"))
+    }
+}
+
+/// Resolve any input accepted by PlaylistInput::parse to the id of a playlist.
+///
+/// Channels are mapped to their "All Videos" playlist. If the channel id is not part of the input,
+/// yt-dlp is used to look it up, which might take until the timeout.
+pub fn resolve_playlist_id(input: &str, timeout: Duration, throttle: &Throttle) -> Result<String, ResolveError> {
+    let retrieve_channel_id = |url: &str, is_playlist: bool| throttle
+        .request(url, || Ok(yt_dlp::retrieve_channel_id(url, is_playlist, timeout)?));
+
+    let channel_id = match PlaylistInput::parse(input) {
+        Some(PlaylistInput::PlaylistId(id)) => return Ok(id),
+        Some(PlaylistInput::ChannelId(id)) => id,
+        Some(PlaylistInput::ChannelUrl(url)) => retrieve_channel_id(&url, true)?,
+        Some(PlaylistInput::VideoUrl(url)) => retrieve_channel_id(&url, false)?,
+        None => return Err(ResolveError::UnsupportedInput(input.to_string()))
+    };
+
+    if is_channel_id(&channel_id) {
+        Ok(channel_id_to_playlist_id(&channel_id))
+    } else {
+        Err(ResolveError::ChannelNotFound(input.to_string()))
+    }
+}
+
+/// Channel ids start with UC, followed by 22 characters
+fn is_channel_id(id: &str) -> bool {
+    id.len() == 24 && id.starts_with("UC") && is_id(id)
+}
+
+/// Ids and handles consist of letters, digits, '-', '_' and '.'
+fn is_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The "All Videos" playlist of a channel has the same id as the channel, but starts with UU instead of UC
+fn channel_id_to_playlist_id(channel_id: &str) -> String {
+    format!("UU{}", &channel_id[2..])
+}
+
+#[error]
+pub enum ResolveError {
+    #[error(message = "{_0}", impl_from)]
+    VideoSourceError(VideoSourceError),
+    #[error(message = "'{_0}' is neither a playlist id, a channel id nor a supported YouTube URL")]
+    UnsupportedInput(String),
+    #[error(message = "Could not find the channel of '{_0}'")]
+    ChannelNotFound(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tube_service::throttle::FakeClock;
+
+    fn parse(input: &str) -> Option<PlaylistInput> {
+        PlaylistInput::parse(input)
+    }
+
+    fn channel_url(url: &str) -> Option<PlaylistInput> {
+        Some(PlaylistInput::ChannelUrl(url.to_string()))
+    }
+
+    fn video_url(video_id: &str) -> Option<PlaylistInput> {
+        Some(PlaylistInput::VideoUrl(format!("https://www.youtube.com/watch?v={video_id}")))
+    }
+
+    #[test]
+    fn parse_playlist_ids() {
+        let playlist = Some(PlaylistInput::PlaylistId("PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf".to_string()));
+
+        assert_eq!(parse("PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"), playlist);
+        assert_eq!(parse("  PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf\n"), playlist);
+        assert_eq!(parse("https://www.youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"), playlist);
+        assert_eq!(parse("https://music.youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf&si=abc"), playlist);
+        // a video in a playlist stands for the playlist
+        assert_eq!(parse("https://www.youtube.com/watch?v=a5xvRqg9yDc&list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"), playlist);
+    }
+
+    #[test]
+    fn parse_channel_ids() {
+        let channel = Some(PlaylistInput::ChannelId("UCaaaaaaaaaaaaaaaaaaaaaa".to_string()));
+
+        assert_eq!(parse("UCaaaaaaaaaaaaaaaaaaaaaa"), channel);
+        assert_eq!(parse("https://www.youtube.com/channel/UCaaaaaaaaaaaaaaaaaaaaaa"), channel);
+        assert_eq!(parse("youtube.com/channel/UCaaaaaaaaaaaaaaaaaaaaaa/videos"), channel);
+    }
+
+    #[test]
+    fn parse_channel_urls() {
+        assert_eq!(parse("https://www.youtube.com/c/WoodWorks"), channel_url("https://www.youtube.com/c/WoodWorks/videos"));
+        assert_eq!(parse("https://www.youtube.com/user/woodworks/featured"), channel_url("https://www.youtube.com/user/woodworks/videos"));
+    }
+
+    #[test]
+    fn parse_handles() {
+        let handle = channel_url("https://www.youtube.com/@wood.works/videos");
+
+        assert_eq!(parse("@wood.works"), handle);
+        assert_eq!(parse("https://www.youtube.com/@wood.works"), handle);
+        assert_eq!(parse("https://m.youtube.com/@wood.works/streams"), handle);
+    }
+
+    #[test]
+    fn parse_video_urls() {
+        assert_eq!(parse("https://www.youtube.com/watch?v=a5xvRqg9yDc"), video_url("a5xvRqg9yDc"));
+        assert_eq!(parse("https://youtu.be/a5xvRqg9yDc?t=42"), video_url("a5xvRqg9yDc"));
+        assert_eq!(parse("https://www.youtube.com/shorts/a5xvRqg9yDc"), video_url("a5xvRqg9yDc"));
+        assert_eq!(parse("https://www.youtube.com/live/a5xvRqg9yDc"), video_url("a5xvRqg9yDc"));
+    }
+
+    #[test]
+    fn parse_rejects_other_inputs() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(parse("@"), None);
+        assert_eq!(parse("not a playlist"), None);
+        assert_eq!(parse("https://example.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"), None);
+        assert_eq!(parse("https://vimeo.com/123456"), None);
+        assert_eq!(parse("https://www.youtube.com/"), None);
+        assert_eq!(parse("https://www.youtube.com/watch"), None);
+        assert_eq!(parse("https://www.youtube.com/feed/subscriptions"), None);
+        assert_eq!(parse("https://www.youtube.com/channel/not-a-channel-id"), None);
+    }
+
+    #[test]
+    fn resolve_maps_channels_to_their_uploads_playlist() {
+        let resolve = |input| resolve_playlist_id(input, Duration::from_secs(1), &Throttle::new(Default::default(), FakeClock::new()));
+
+        assert_eq!(resolve("UCaaaaaaaaaaaaaaaaaaaaaa").unwrap(), "UUaaaaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(resolve("PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf").unwrap(), "PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf");
+        assert!(matches!(resolve("not a playlist"), Err(ResolveError::UnsupportedInput(_))));
+    }
+
+    #[test]
+    fn resolve_looks_up_channels_through_the_throttle() {
+        let throttle = Throttle::new(ron::from_str("(retry_attempts: 0, pause_after_rate_limits: 1)").unwrap(), FakeClock::new());
+        let rate_limited = throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || -> Result<(), VideoSourceError> {
+            Err(VideoSourceError::YTDLPError(yt_dlp::Error::RateLimited("HTTP Error 429".to_string())))
+        });
+        assert!(rate_limited.is_err());
+
+        // yt-dlp is not even started while the requests are paused
+        let result = resolve_playlist_id("@wood.works", Duration::from_secs(1), &throttle);
+        assert!(matches!(result, Err(ResolveError::VideoSourceError(VideoSourceError::Paused(_)))));
+    }
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Output};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::shutdown;
+
+/// How often a running process is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long the output is read after the process exited. Processes it started in the background
+/// might still hold the pipes open, so the output read until then is used.
+const OUTPUT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The ids of all running processes, so they can be killed when new_tube stops.
+/// On unix, every process leads a process group of its own, so this is the id of its group too.
+static RUNNING_PROCESSES: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// A process started by new_tube, like yt-dlp or a hook. It is killed when new_tube stops.
+///
+/// On unix, the process runs in its own process group, as it might start other processes (like ffmpeg).
+/// It does not get the signals which stop new_tube, but the whole group is killed instead.
+/// On other platforms, only the process itself is killed after a timeout.
+pub struct RunningProcess {
+    child: Child,
+    stdout_reader: Reader,
+    stderr_reader: Reader,
+}
+
+impl RunningProcess {
+    /// Start the command. Its piped stdout and stderr are read in the background,
+    /// so a process which writes a lot of output does not block.
+    ///
+    /// Nothing is started once new_tube is stopping.
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        #[cfg(unix)]
+        command.process_group(0);
+
+        // the processes are locked while starting, so a process is either killed on shutdown or not started at all
+        let mut running = RUNNING_PROCESSES.lock().expect("the running processes should not be poisoned");
+
+        if shutdown::requested() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "new_tube is stopping"));
+        }
+
+        let mut child = command.spawn()?;
+        running.push(child.id());
+
+        let stdout_reader = read_in_background(child.stdout.take());
+        let stderr_reader = read_in_background(child.stderr.take());
+
+        Ok(RunningProcess {
+            child,
+            stdout_reader,
+            stderr_reader,
+        })
+    }
+
+    /// The stdin of the process, if it is piped
+    pub fn stdin(&mut self) -> Option<ChildStdin> {
+        self.child.stdin.take()
+    }
+
+    /// Wait until the process exits and return its output.
+    /// If it takes longer than the timeout, it is killed and None is returned.
+    pub fn wait_with_timeout(mut self, timeout: Duration) -> io::Result<Option<Output>> {
+        let start = Instant::now();
+        let mut killed = false;
+
+        let status = loop {
+            if let Some(status) = self.try_wait()? {
+                break status;
+            }
+
+            if !killed && start.elapsed() >= timeout {
+                kill(&mut self.child)?;
+                killed = true;
+            }
+
+            thread::sleep(POLL_INTERVAL)
+        };
+
+        let stdout = self.stdout_reader.finish();
+        let stderr = self.stderr_reader.finish();
+
+        Ok((!killed).then_some(Output { status, stdout, stderr }))
+    }
+
+    /// Check if the process exited. Once it is reaped, its id might be reused by the system,
+    /// so it is no longer one of the running processes which are killed when new_tube stops.
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        let mut running = RUNNING_PROCESSES.lock().expect("the running processes should not be poisoned");
+        let status = self.child.try_wait()?;
+
+        if status.is_some() {
+            let id = self.child.id();
+            running.retain(|process| *process != id);
+        }
+
+        Ok(status)
+    }
+}
+
+/// Reads a pipe of a process in the background
+struct Reader {
+    output: Arc<Mutex<Vec<u8>>>,
+    /// Disconnected once the whole output was read
+    done: Receiver<()>,
+}
+
+impl Reader {
+    /// Wait a moment for the rest of the output and return everything read so far
+    fn finish(self) -> Vec<u8> {
+        let _ = self.done.recv_timeout(OUTPUT_TIMEOUT);
+        std::mem::take(&mut *self.output.lock().expect("the output should not be poisoned"))
+    }
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> Reader {
+    let output = Arc::new(Mutex::new(vec![]));
+    let (sender, done) = channel::<()>();
+
+    let shared_output = output.clone();
+    thread::spawn(move || {
+        let _sender = sender;
+        let mut buffer = [0; 8192];
+
+        if let Some(mut pipe) = pipe {
+            while let Ok(read @ 1..) = pipe.read(&mut buffer) {
+                shared_output.lock().expect("the output should not be poisoned").extend_from_slice(&buffer[..read]);
+            }
+        }
+    });
+
+    Reader { output, done }
+}
+
+/// Kill all running processes and everything they started. Used when new_tube stops.
+///
+/// Only needed on unix. On other platforms, the processes share the console of new_tube and stop with it.
+pub fn kill_running_processes() {
+    for process in RUNNING_PROCESSES.lock().expect("the running processes should not be poisoned").drain(..) {
+        #[cfg(unix)]
+        kill_process_group(process);
+        #[cfg(not(unix))]
+        let _ = process;
+    }
+}
+
+#[cfg(unix)]
+fn kill(child: &mut Child) -> io::Result<()> {
+    kill_process_group(child.id());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+#[cfg(unix)]
+fn kill_process_group(process_group: u32) {
+    // a negative pid addresses the whole process group
+    // SAFETY: kill only sends a signal and does not touch any memory of new_tube. The group is still running
+    // (or a zombie), as its id is removed from the running processes while they are locked, before it is reaped.
+    unsafe {
+        libc::kill(-(process_group as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::process::Stdio;
+
+    use super::*;
+
+    #[test]
+    fn the_output_is_returned() {
+        let process = RunningProcess::spawn(Command::new("sh")
+            .args(["-c", "echo out; echo err >&2; exit 2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())).unwrap();
+
+        let output = process.wait_with_timeout(Duration::from_secs(10)).unwrap().unwrap();
+
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn processes_which_take_too_long_are_killed_with_everything_they_started() {
+        let start = Instant::now();
+        // the shell keeps running sleep in the background, which holds the pipes open until it is killed too
+        let process = RunningProcess::spawn(Command::new("sh")
+            .args(["-c", "sleep 30 & sleep 30; wait"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())).unwrap();
+
+        assert!(process.wait_with_timeout(Duration::from_millis(200)).unwrap().is_none());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn processes_started_in_the_background_do_not_block_the_output() {
+        let start = Instant::now();
+        // the background sleep holds the pipes open after the shell exited
+        let process = RunningProcess::spawn(Command::new("sh")
+            .args(["-c", "sleep 30 & echo out"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())).unwrap();
+        let id = process.child.id();
+
+        let output = process.wait_with_timeout(Duration::from_secs(10)).unwrap().unwrap();
+
+        assert_eq!(output.stdout, b"out\n");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!RUNNING_PROCESSES.lock().unwrap().contains(&id));
+        kill_process_group(id);
+    }
+}
@@ -0,0 +1,555 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use crate::config::Config;
+use crate::new_tube_service::database::Database;
+use crate::mock_server::MockServer;
+use crate::new_tube_service::fixture_source::FixtureSource;
+use crate::new_tube_service::invidious::InvidiousSource;
+use crate::new_tube_service::piped::PipedSource;
+use crate::new_tube_service::throttle::{FakeClock, Throttle};
+use crate::new_tube_service::video_source::{Result as SourceResult, VideoSource};
+use crate::new_tube_service::yt_dlp::YTDLPItem;
+use crate::new_tube_service::{NewTubeService, NewTubeServiceError, NewVideo};
+use crate::playlist_item::{PlaylistItem, VideoKind};
+use crate::user::User;
+
+const FIXTURE: &str = include_str!("../../tests/fixtures/yt_dlp_playlists.jsonl");
+const WOOD_WORKS: &str = "UUaaaaaaaaaaaaaaaaaaaaaa";
+const RADIO: &str = "UUbbbbbbbbbbbbbbbbbbbbbb";
+const DEFAULT_CHAT: i64 = -1;
+
+fn service(source: &FixtureSource) -> NewTubeService {
+    let config = ron::from_str::<Config>("(bot_fetch_schedule: 5)").unwrap();
+    NewTubeService::with_video_source(&config, Database::open_in_memory().unwrap(), Box::new(source.clone()), throttle())
+}
+
+fn throttle() -> Arc<Throttle> {
+    Arc::new(Throttle::new(Default::default(), FakeClock::new()))
+}
+
+fn user(service: &NewTubeService, id: i64) -> User {
+    let user = User {
+        id,
+        username: format!("user{id}"),
+        chat_id: id * 10,
+        approved: true,
+        admin: false,
+        links: None,
+    };
+    service.save_user(&user).unwrap();
+    user
+}
+
+fn chat_ids(service: &NewTubeService, playlist_id: &str) -> Vec<i64> {
+    chat_ids_of(service, &PlaylistItem::new(item(playlist_id, "video"), String::new()))
+}
+
+fn chat_ids_of(service: &NewTubeService, video: &PlaylistItem) -> Vec<i64> {
+    service
+        .get_chats_to_notify(video, DEFAULT_CHAT)
+        .unwrap()
+        .into_iter()
+        .map(|(chat_id, _)| chat_id)
+        .collect()
+}
+
+fn item(playlist_id: &str, id: &str) -> YTDLPItem {
+    YTDLPItem {
+        playlist_id: playlist_id.to_string(),
+        id: id.to_string(),
+        title: format!("Video {id}"),
+        duration: Some(600.0),
+        channel: "Wood Works".to_string(),
+        live_status: None,
+        release_timestamp: None,
+        url: None,
+        width: None,
+        height: None,
+    }
+}
+
+fn live_item(playlist_id: &str, id: &str) -> YTDLPItem {
+    YTDLPItem {
+        live_status: Some("is_live".to_string()),
+        ..item(playlist_id, id)
+    }
+}
+
+/// The fixture items of the playlist with new uploads in front of them, newest first
+fn with_uploads(source: &FixtureSource, playlist_id: &str, new_ids: &[&str]) -> Vec<YTDLPItem> {
+    new_ids
+        .iter()
+        .map(|id| item(playlist_id, id))
+        .chain(source.items(playlist_id))
+        .collect()
+}
+
+/// The last known state of a playlist
+fn last(video_id: &str, previous_video_id: &str) -> PlaylistItem {
+    PlaylistItem::new(item(WOOD_WORKS, video_id), previous_video_id.to_string())
+}
+
+fn ids(videos: &[PlaylistItem]) -> Vec<&str> {
+    videos.iter().map(|video| video.video_id.as_str()).collect()
+}
+
+fn assert_really_new(new: NewVideo, expected: &[&str]) {
+    match new {
+        NewVideo::ReallyNew(videos) => assert_eq!(ids(&videos), expected),
+        _ => panic!("expected new videos")
+    }
+}
+
+#[test]
+fn get_new_video_nothing_changed() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let items = source.items(WOOD_WORKS);
+
+    assert!(matches!(NewTubeService::get_new_video(&items, &last("a5xvRqg9yDc", "b4PLqW0XkTs")), NewVideo::SameAsBefore));
+}
+
+#[test]
+fn get_new_video_finds_every_upload_since_the_last_one() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let items = with_uploads(&source, WOOD_WORKS, &["new2", "new1"]);
+    let new = NewTubeService::get_new_video(&items, &last("a5xvRqg9yDc", "b4PLqW0XkTs"));
+
+    match new {
+        NewVideo::ReallyNew(videos) => {
+            assert_eq!(ids(&videos), ["new1", "new2"]);
+            assert_eq!(videos[0].previous_video_id, "a5xvRqg9yDc");
+            assert_eq!(videos[1].previous_video_id, "new1");
+        }
+        _ => panic!("expected new videos")
+    }
+}
+
+#[test]
+fn get_new_video_latest_video_was_removed() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let items = source.items(WOOD_WORKS);
+
+    match NewTubeService::get_new_video(&items, &last("removed", "a5xvRqg9yDc")) {
+        NewVideo::OldVideoNowLatest(video) => assert_eq!(video.video_id, "a5xvRqg9yDc"),
+        _ => panic!("expected the previous video to be the latest one")
+    }
+}
+
+#[test]
+fn get_new_video_latest_video_was_removed_and_new_ones_uploaded() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let items = with_uploads(&source, WOOD_WORKS, &["new"]);
+
+    assert_really_new(NewTubeService::get_new_video(&items, &last("removed", "a5xvRqg9yDc")), &["new"]);
+}
+
+#[test]
+fn get_new_video_only_reports_the_latest_video_if_the_anchor_is_lost() {
+    let items = vec![item(WOOD_WORKS, "x3"), item(WOOD_WORKS, "x2"), item(WOOD_WORKS, "x1")];
+
+    assert_really_new(NewTubeService::get_new_video(&items, &last("gone", "gone too")), &["x3"]);
+}
+
+#[test]
+fn get_new_video_keeps_the_state_if_nothing_was_fetched() {
+    assert!(matches!(NewTubeService::get_new_video(&[], &last("a5xvRqg9yDc", "b4PLqW0XkTs")), NewVideo::SameAsBefore));
+    assert!(matches!(NewTubeService::get_new_video(&[], &PlaylistItem::empty(WOOD_WORKS)), NewVideo::SameAsBefore));
+}
+
+#[test]
+fn get_new_video_only_reports_the_latest_video_of_a_playlist_which_was_empty() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+
+    assert_really_new(NewTubeService::get_new_video(&source.items(WOOD_WORKS), &PlaylistItem::empty(WOOD_WORKS)), &["a5xvRqg9yDc"]);
+    assert_really_new(NewTubeService::get_new_video(&[item(WOOD_WORKS, "first")], &PlaylistItem::empty(WOOD_WORKS)), &["first"]);
+}
+
+#[test]
+fn get_new_video_of_a_playlist_with_a_single_video() {
+    // the last known video has no previous one
+    assert_really_new(NewTubeService::get_new_video(&[item(WOOD_WORKS, "new"), item(WOOD_WORKS, "only")], &last("only", "")), &["new"]);
+    // the only video was replaced
+    assert_really_new(NewTubeService::get_new_video(&[item(WOOD_WORKS, "new")], &last("only", "")), &["new"]);
+}
+
+#[test]
+fn get_new_video_finds_an_anchor_at_the_end_of_the_fetched_items() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let items = source.items(WOOD_WORKS);
+
+    assert_really_new(NewTubeService::get_new_video(&items, &last("e1Zt7bVcR3g", "")), &["d2Hn5mYpQ1o", "c3kDuf8sLwE", "b4PLqW0XkTs", "a5xvRqg9yDc"]);
+}
+
+#[test]
+fn get_new_video_the_latest_and_the_previous_video_were_removed() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let items = source.items(WOOD_WORKS)[2..].to_vec();
+
+    assert_really_new(NewTubeService::get_new_video(&items, &last("a5xvRqg9yDc", "b4PLqW0XkTs")), &["c3kDuf8sLwE"]);
+}
+
+#[test]
+fn fetch_does_not_flood_after_an_empty_response_while_adding() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    let items = source.items(WOOD_WORKS);
+
+    // the source returns nothing by mistake
+    source.set_items(WOOD_WORKS, vec![]);
+    service.add_playlist(WOOD_WORKS).unwrap();
+    assert!(service.get_last_items().unwrap().is_empty());
+
+    // as long as it keeps doing so, nothing changes
+    assert!(service.get_new_videos_and_update_database().unwrap().new_videos.is_empty());
+
+    source.set_items(WOOD_WORKS, items);
+    assert_eq!(ids(&service.get_new_videos_and_update_database().unwrap().new_videos), ["a5xvRqg9yDc"]);
+    assert!(service.get_new_videos_and_update_database().unwrap().new_videos.is_empty());
+}
+
+#[test]
+fn fetch_reports_the_first_upload_of_an_empty_playlist() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    source.set_items(WOOD_WORKS, vec![]);
+    service.add_playlist(WOOD_WORKS).unwrap();
+
+    source.set_items(WOOD_WORKS, vec![item(WOOD_WORKS, "first")]);
+    assert_eq!(ids(&service.get_new_videos_and_update_database().unwrap().new_videos), ["first"]);
+
+    source.set_items(WOOD_WORKS, vec![item(WOOD_WORKS, "third"), item(WOOD_WORKS, "second"), item(WOOD_WORKS, "first")]);
+    assert_eq!(ids(&service.get_new_videos_and_update_database().unwrap().new_videos), ["second", "third"]);
+}
+
+#[test]
+fn hooks_run_in_the_background_for_every_new_video() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let config = ron::from_str::<Config>(r#"(bot_fetch_schedule: 5, hooks: [(command: "false")])"#).unwrap();
+    let service = NewTubeService::with_video_source(&config, Database::open_in_memory().unwrap(), Box::new(source.clone()), throttle());
+    service.add_playlist(WOOD_WORKS).unwrap();
+
+    source.set_items(WOOD_WORKS, with_uploads(&source, WOOD_WORKS, &["new2", "new1"]));
+    let result = service.get_new_videos_and_update_database().unwrap();
+    assert_eq!(ids(&result.new_videos), ["new1", "new2"]);
+
+    let failures = service.finish_hooks();
+    let mut video_ids: Vec<&str> = failures.iter().map(|failure| failure.video_id.as_str()).collect();
+    video_ids.sort();
+    assert_eq!(video_ids, ["new1", "new2"]);
+}
+
+#[test]
+fn fetch_stores_and_reports_new_videos() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    service.add_playlist(WOOD_WORKS).unwrap();
+    service.add_playlist(RADIO).unwrap();
+
+    let result = service.get_new_videos_and_update_database().unwrap();
+    assert!(result.new_videos.is_empty());
+    assert!(result.failures.is_empty());
+
+    source.set_items(WOOD_WORKS, with_uploads(&source, WOOD_WORKS, &["new2", "new1"]));
+    let result = service.get_new_videos_and_update_database().unwrap();
+    assert_eq!(ids(&result.new_videos), ["new1", "new2"]);
+
+    let latest = service.get_last_items().unwrap();
+    let wood_works = latest.iter().find(|item| item.playlist_id == WOOD_WORKS).unwrap();
+    assert_eq!((wood_works.video_id.as_str(), wood_works.previous_video_id.as_str()), ("new2", "new1"));
+    assert_eq!(ids(&service.get_history(Some("Wood Works"), None).unwrap()).len(), 3);
+
+    // nothing is reported twice
+    assert!(service.get_new_videos_and_update_database().unwrap().new_videos.is_empty());
+}
+
+#[test]
+fn fetch_collects_failures_without_stopping_the_others() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    service.add_playlist(WOOD_WORKS).unwrap();
+    service.add_playlist(RADIO).unwrap();
+
+    source.set_failing(RADIO);
+    source.set_items(WOOD_WORKS, with_uploads(&source, WOOD_WORKS, &["new"]));
+    let result = service.get_new_videos_and_update_database().unwrap();
+
+    assert_eq!(ids(&result.new_videos), ["new"]);
+    assert_eq!(result.failures.len(), 1);
+    assert_eq!(result.failures[0].playlist_id, RADIO);
+
+    let status = service.get_statuses().unwrap().into_iter().find(|status| status.playlist_id == RADIO).unwrap();
+    assert_eq!(status.consecutive_failures, 1);
+}
+
+#[test]
+fn unsubscribing_deletes_playlists_of_the_bot_after_the_last_subscriber() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    let (first, second) = (user(&service, 1), user(&service, 2));
+
+    service.subscribe(first.id, WOOD_WORKS).unwrap();
+    service.subscribe(second.id, WOOD_WORKS).unwrap();
+    assert_eq!(chat_ids(&service, WOOD_WORKS), [10, 20]);
+
+    service.unsubscribe(first.id, WOOD_WORKS).unwrap();
+    assert!(service.get_playlist_ids().unwrap().contains(&WOOD_WORKS.to_string()));
+
+    service.unsubscribe(second.id, WOOD_WORKS).unwrap();
+    assert!(service.get_playlist_ids().unwrap().is_empty());
+}
+
+#[test]
+fn replacing_a_subscription_requires_the_old_one() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    let user = user(&service, 1);
+
+    assert!(matches!(service.replace_subscription(user.id, WOOD_WORKS, RADIO), Err(NewTubeServiceError::NotSubscribed(_))));
+    assert!(service.get_subscriptions(user.id).unwrap().is_empty());
+    assert!(service.get_playlist_ids().unwrap().is_empty());
+
+    service.subscribe(user.id, WOOD_WORKS).unwrap();
+    service.replace_subscription(user.id, WOOD_WORKS, WOOD_WORKS).unwrap();
+    assert_eq!(service.get_subscriptions(user.id).unwrap(), [WOOD_WORKS]);
+
+    service.replace_subscription(user.id, WOOD_WORKS, RADIO).unwrap();
+    assert_eq!(service.get_subscriptions(user.id).unwrap(), [RADIO]);
+    assert_eq!(service.get_playlist_ids().unwrap(), [RADIO]);
+}
+
+#[test]
+fn unsubscribing_never_deletes_playlists_of_the_admin() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    let user = user(&service, 1);
+    service.add_playlist(WOOD_WORKS).unwrap();
+
+    // users who never subscribed cannot delete the playlist
+    assert!(service.unsubscribe(user.id, WOOD_WORKS).is_err());
+    assert_eq!(chat_ids(&service, WOOD_WORKS), [DEFAULT_CHAT]);
+
+    // the default chat keeps getting the videos if users subscribe
+    service.subscribe(user.id, WOOD_WORKS).unwrap();
+    assert_eq!(chat_ids(&service, WOOD_WORKS), [10, DEFAULT_CHAT]);
+
+    service.unsubscribe(user.id, WOOD_WORKS).unwrap();
+    service.delete_user(user.id).unwrap();
+    assert_eq!(service.get_playlist_ids().unwrap(), [WOOD_WORKS]);
+}
+
+#[test]
+fn muting_a_kind_only_affects_the_user() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    let (first, second) = (user(&service, 1), user(&service, 2));
+    service.subscribe(first.id, RADIO).unwrap();
+    service.subscribe(second.id, RADIO).unwrap();
+
+    service.set_kind_notification(Some(first.id), RADIO, VideoKind::Live, false).unwrap();
+
+    let live = PlaylistItem::new(live_item(RADIO, "live"), String::new());
+    assert_eq!(live.kind, VideoKind::Live);
+    assert_eq!(chat_ids_of(&service, &live), [20]);
+    assert_eq!(chat_ids(&service, RADIO), [10, 20]);
+
+    // the setting for everyone is applied while fetching
+    service.set_kind_notification(None, RADIO, VideoKind::Live, false).unwrap();
+    source.set_items(RADIO, [live_item(RADIO, "live")].into_iter().chain(source.items(RADIO)).collect());
+    let result = service.get_new_videos_and_update_database().unwrap();
+    assert!(result.new_videos.is_empty());
+    assert_eq!(ids(&result.muted_videos), ["live"]);
+}
+
+#[test]
+fn users_only_see_and_remove_their_own_filters() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    let (first, second) = (user(&service, 1), user(&service, 2));
+    let admin = User { admin: true, ..user(&service, 3) };
+    service.subscribe(first.id, WOOD_WORKS).unwrap();
+    service.subscribe(second.id, WOOD_WORKS).unwrap();
+
+    let filter = service.add_filter(Some(first.id), WOOD_WORKS, "title-include", "(?i)workbench").unwrap();
+    let filter_ids = |user: &User| service.get_filters(Some(user), None).unwrap().iter().map(|filter| filter.id).collect::<Vec<_>>();
+
+    assert_eq!(filter_ids(&first), [filter]);
+    assert!(filter_ids(&second).is_empty());
+    assert_eq!(filter_ids(&admin), [filter]);
+
+    // the filter only applies to the notifications of its user
+    let chisels = PlaylistItem::new(source.items(WOOD_WORKS)[3].clone(), String::new());
+    assert_eq!(chat_ids_of(&service, &chisels), [20]);
+
+    assert!(service.remove_filter(Some(&second), filter).is_err());
+    service.remove_filter(Some(&first), filter).unwrap();
+    assert!(filter_ids(&admin).is_empty());
+}
+
+#[test]
+fn filters_for_everyone_are_applied_while_fetching() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let service = service(&source);
+    service.add_playlist(WOOD_WORKS).unwrap();
+    service.add_filter(None, WOOD_WORKS, "title-exclude", "(?i)trailer").unwrap();
+
+    source.set_items(WOOD_WORKS, with_uploads(&source, WOOD_WORKS, &["Trailer", "Part 4"]));
+    let result = service.get_new_videos_and_update_database().unwrap();
+
+    assert_eq!(ids(&result.new_videos), ["Part 4"]);
+    // nobody is notified about filtered videos, but they are downloaded
+    assert_eq!(ids(&result.muted_videos), ["Trailer"]);
+    // filtered videos are saved anyway, so they are not new on the next fetch
+    assert_eq!(service.get_history(None, None).unwrap().len(), 3);
+}
+
+/// A source which stops in the middle of every fetch until the test lets it go on
+struct WaitingSource {
+    source: FixtureSource,
+    started: Arc<Barrier>,
+    release: Arc<Barrier>,
+}
+
+impl VideoSource for WaitingSource {
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> SourceResult<Vec<YTDLPItem>> {
+        self.started.wait();
+        self.release.wait();
+        self.source.retrieve_latest_items(playlist_id, amount)
+    }
+}
+
+#[test]
+fn the_service_answers_while_fetching() {
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    let (started, release) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+    let service = service(&source);
+    service.add_playlist(WOOD_WORKS).unwrap();
+    let service = NewTubeService::with_video_source(
+        &ron::from_str::<Config>("(bot_fetch_schedule: 5)").unwrap(),
+        service.database.into_inner().unwrap(),
+        Box::new(WaitingSource { source, started: started.clone(), release: release.clone() }),
+        throttle(),
+    );
+
+    thread::scope(|scope| {
+        let fetch = scope.spawn(|| service.get_new_videos_and_update_database().unwrap());
+        started.wait();
+
+        // the fetch is waiting for the source right now
+        assert_eq!(service.get_playlist_ids().unwrap(), [WOOD_WORKS]);
+        user(&service, 1);
+
+        release.wait();
+        assert!(fetch.join().unwrap().failures.is_empty());
+    });
+}
+
+#[test]
+fn playlists_deleted_while_fetching_stay_deleted() {
+    const SPORTS: &str = "UUcccccccccccccccccccccc";
+    let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+    source.set_items(SPORTS, vec![item(SPORTS, "old")]);
+    let service = service(&source);
+    for playlist_id in [WOOD_WORKS, RADIO, SPORTS] {
+        service.add_playlist(playlist_id).unwrap();
+    }
+
+    source.set_items(WOOD_WORKS, with_uploads(&source, WOOD_WORKS, &["deleted"]));
+    source.set_failing(RADIO);
+    source.set_items(SPORTS, vec![item(SPORTS, "new"), item(SPORTS, "old")]);
+    // all three playlists are fetched at the same time
+    let (started, release) = (Arc::new(Barrier::new(4)), Arc::new(Barrier::new(4)));
+    let service = NewTubeService::with_video_source(
+        &ron::from_str::<Config>("(bot_fetch_schedule: 5)").unwrap(),
+        service.database.into_inner().unwrap(),
+        Box::new(WaitingSource { source, started: started.clone(), release: release.clone() }),
+        throttle(),
+    );
+
+    thread::scope(|scope| {
+        let fetch = scope.spawn(|| service.get_new_videos_and_update_database().unwrap());
+        started.wait();
+
+        service.delete(WOOD_WORKS).unwrap();
+        service.delete(RADIO).unwrap();
+
+        release.wait();
+        let result = fetch.join().unwrap();
+        assert_eq!(ids(&result.new_videos), ["new"]);
+        assert!(result.failures.is_empty());
+    });
+
+    assert_eq!(service.get_playlist_ids().unwrap(), [SPORTS]);
+}
+
+#[test]
+fn history_accepts_valid_dates() {
+    let service = service(&FixtureSource::from_yt_dlp_output(FIXTURE));
+
+    for date in ["2026-10-18", "2024-02-29", "2000-02-29", "1999-12-31"] {
+        assert!(service.get_history(None, Some(date)).is_ok(), "{date}");
+    }
+}
+
+#[test]
+fn history_rejects_invalid_dates() {
+    let service = service(&FixtureSource::from_yt_dlp_output(FIXTURE));
+
+    for date in ["yesterday", "2026-13-01", "2026-00-10", "2026-04-31", "2023-02-29", "1900-02-29", "2026-1-18", "26-10-18", "2026-10-18 12:00", "+026-10-18", ""] {
+        assert!(matches!(service.get_history(None, Some(date)), Err(NewTubeServiceError::InvalidDate(_))), "{date}");
+    }
+}
+
+/// A premiere, an upcoming and a running livestream and a finished one, newest first, as every source reports them
+fn items_of_every_source() -> Vec<Vec<YTDLPItem>> {
+    let yt_dlp = [("premiere", "is_upcoming", Some(912.0)), ("upcoming", "is_upcoming", None), ("live", "is_live", None), ("finished", "was_live", Some(3605.0))]
+        .into_iter()
+        .map(|(id, live_status, duration)| YTDLPItem { live_status: Some(live_status.to_string()), duration, ..item(RADIO, id) })
+        .chain([item(RADIO, "old")])
+        .collect();
+
+    let invidious = MockServer::respond_with(200, r#"{"videos": [
+        {"videoId": "premiere", "title": "", "author": "", "lengthSeconds": 912, "isUpcoming": true, "premiereTimestamp": 4102444800},
+        {"videoId": "upcoming", "title": "", "author": "", "lengthSeconds": 0, "isUpcoming": true, "published": 4103049600},
+        {"videoId": "live", "title": "", "author": "", "lengthSeconds": 0, "liveNow": true},
+        {"videoId": "finished", "title": "", "author": "", "lengthSeconds": 3605},
+        {"videoId": "old", "title": "", "author": "", "lengthSeconds": 600}
+    ]}"#);
+    let piped = MockServer::respond_with(200, r#"{"relatedStreams": [
+        {"url": "/watch?v=premiere", "title": "", "uploaderName": "", "duration": 912, "uploaded": 4102444800000},
+        {"url": "/watch?v=upcoming", "title": "", "uploaderName": "", "duration": -1, "uploaded": 4103049600000},
+        {"url": "/watch?v=live", "title": "", "uploaderName": "", "duration": -1, "uploaded": -1},
+        {"url": "/watch?v=finished", "title": "", "uploaderName": "", "duration": 3605, "uploadedDate": "Streamed 6 days ago"},
+        {"url": "/watch?v=old", "title": "", "uploaderName": "", "duration": 600}
+    ]}"#);
+
+    vec![
+        yt_dlp,
+        InvidiousSource::new(&invidious.url).retrieve_latest_items(RADIO, 10).unwrap(),
+        PipedSource::new(&piped.url).retrieve_latest_items(RADIO, 10).unwrap(),
+    ]
+}
+
+#[test]
+fn switching_the_source_does_not_change_the_kinds() {
+    let sources = items_of_every_source();
+
+    for first in 0..sources.len() {
+        let source = FixtureSource::from_yt_dlp_output(FIXTURE);
+        source.set_items(RADIO, vec![item(RADIO, "old")]);
+        let service = service(&source);
+        service.add_playlist(RADIO).unwrap();
+
+        source.set_items(RADIO, sources[first].clone());
+        let new_videos = service.get_new_videos_and_update_database().unwrap().new_videos;
+        // only yt-dlp and Piped know that the oldest one was a livestream
+        let kinds = new_videos.iter().map(|video| video.kind).collect::<Vec<_>>();
+        assert_eq!(kinds[1..], [VideoKind::Live, VideoKind::Upcoming, VideoKind::Premiere], "{first}");
+
+        // the videos are fetched from the other sources, like when the first one fails
+        for items in sources.iter().cycle().skip(first + 1).take(sources.len()) {
+            source.set_items(RADIO, items.clone());
+            assert!(service.get_new_videos_and_update_database().unwrap().new_videos.is_empty(), "{first}");
+        }
+    }
+}
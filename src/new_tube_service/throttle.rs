@@ -0,0 +1,350 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::ThrottleConfig;
+use crate::new_tube_service::video_source::{Result, VideoSource, VideoSourceError};
+use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Where the throttle gets the time from and how it waits, so the time can be faked
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A clock for tests, whose time only passes if something sleeps or the test advances it. Clones share their time
+#[cfg(test)]
+#[derive(Clone)]
+pub struct FakeClock {
+    start: Instant,
+    /// How much time passed, and the durations of every sleep
+    time: Arc<Mutex<(Duration, Vec<Duration>)>>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new() -> Self {
+        FakeClock {
+            start: Instant::now(),
+            time: Arc::new(Mutex::new((Duration::ZERO, vec![]))),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.time.lock().unwrap().0 += duration
+    }
+
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.time.lock().unwrap().1.clone()
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.time.lock().unwrap().0
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.0 += duration;
+        time.1.push(duration);
+    }
+}
+
+/// Protects YouTube (and the user from being blocked by it) from too many requests.
+///
+/// - Requests which failed due to transient errors, like rate limiting, are retried with an exponential backoff.
+///   A random jitter is added to the delays, so parallel fetches do not retry at the same time.
+/// - All requests, including the retries, share one limit of requests per minute.
+/// - If too many requests in a row were rate limited, all requests are paused for a while. During the pause,
+///   requests fail immediately with VideoSourceError::Paused.
+///
+/// The throttle is shared by everything which sends requests, and wraps every single request, so a fetch
+/// which asks an instance and then falls back to yt-dlp counts as two requests.
+pub struct Throttle {
+    clock: Box<dyn Clock>,
+    config: ThrottleConfig,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    /// When the requests of the last minute were sent, or will be sent if they wait for their turn. Oldest first
+    requests: VecDeque<Instant>,
+    /// How many requests in a row were rate limited
+    consecutive_rate_limits: u32,
+    /// Until when all requests are paused
+    paused_until: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(config: ThrottleConfig, clock: impl Clock + 'static) -> Self {
+        Throttle {
+            clock: Box::new(clock),
+            config,
+            state: Mutex::new(ThrottleState::default()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ThrottleState> {
+        self.state.lock().expect("the throttle state should not be poisoned")
+    }
+
+    /// Fail if the requests are paused
+    fn check_pause(&self) -> Result<()> {
+        let now = self.clock.now();
+
+        match self.state().paused_until {
+            Some(paused_until) if paused_until > now => Err(VideoSourceError::Paused((paused_until - now).as_secs())),
+            _ => Ok(())
+        }
+    }
+
+    /// Wait until another request can be sent without exceeding the requests per minute.
+    ///
+    /// Every request reserves the earliest time it may be sent at, so waiting requests are sent in order.
+    fn wait_for_turn(&self) {
+        let limit = self.config.requests_per_minute as usize;
+
+        if limit == 0 {
+            return;
+        }
+
+        let turn = {
+            let mut state = self.state();
+            let now = self.clock.now();
+
+            while state.requests.front().is_some_and(|sent| *sent + MINUTE <= now) {
+                state.requests.pop_front();
+            }
+
+            let turn = match state.requests.len() {
+                len if len < limit => now,
+                len => (state.requests[len - limit] + MINUTE).max(now)
+            };
+            state.requests.push_back(turn);
+            turn
+        };
+
+        let now = self.clock.now();
+
+        if turn > now {
+            self.clock.sleep(turn - now)
+        }
+    }
+
+    /// Remember the outcome of a request. Pause all requests if too many of them were rate limited in a row.
+    fn record_result<T>(&self, result: &Result<T>) {
+        let mut state = self.state();
+
+        match result {
+            Err(error) if error.is_rate_limited() => {
+                state.consecutive_rate_limits += 1;
+
+                if self.config.pause_after_rate_limits > 0 && state.consecutive_rate_limits >= self.config.pause_after_rate_limits {
+                    println!("YouTube rate limited {} requests in a row, pausing all fetches for {} minutes", state.consecutive_rate_limits, self.config.pause_minutes);
+                    state.paused_until = Some(self.clock.now() + Duration::from_secs(self.config.pause_minutes * 60));
+                    state.consecutive_rate_limits = 0;
+                }
+            }
+            Err(_) => {}
+            Ok(_) => state.consecutive_rate_limits = 0
+        }
+    }
+
+    /// The delay before the given retry (starting at 0): the base delay doubled for every previous retry plus the jitter
+    fn retry_delay(&self, retry: u32) -> Duration {
+        let delay = Duration::from_secs(self.config.retry_base_delay).saturating_mul(2u32.saturating_pow(retry));
+        delay.mul_f32(1.0 + self.config.retry_jitter.max(0.0) * random_fraction())
+    }
+
+    /// Send a single request, described by the subject for the log, like the id of the fetched playlist.
+    /// It waits for its turn and is retried as configured.
+    pub fn request<T>(&self, subject: &str, mut send: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut retry = 0;
+
+        loop {
+            self.check_pause()?;
+            self.wait_for_turn();
+
+            let result = send();
+            self.record_result(&result);
+
+            match result {
+                Err(error) if error.is_transient() && retry < self.config.retry_attempts => {
+                    let delay = self.retry_delay(retry);
+                    println!("Request for {subject} failed, retrying in {} seconds. Reason: {error}", delay.as_secs());
+                    self.clock.sleep(delay);
+                    retry += 1;
+                }
+                result => return result
+            }
+        }
+    }
+}
+
+/// A video source whose requests go through the throttle
+pub struct ThrottledSource {
+    source: Box<dyn VideoSource>,
+    throttle: Arc<Throttle>,
+}
+
+impl ThrottledSource {
+    pub fn new(source: impl VideoSource + 'static, throttle: Arc<Throttle>) -> Self {
+        ThrottledSource {
+            source: Box::new(source),
+            throttle,
+        }
+    }
+}
+
+impl VideoSource for ThrottledSource {
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>> {
+        self.throttle.request(playlist_id, || self.source.retrieve_latest_items(playlist_id, amount))
+    }
+}
+
+/// A random number between 0 and 1. Good enough for jitter, as the hasher is seeded randomly
+fn random_fraction() -> f32 {
+    (RandomState::new().hash_one(Instant::now()) % 1000) as f32 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::new_tube_service::fixture_source::FixtureSource;
+    use crate::new_tube_service::video_source::FallbackSource;
+    use crate::new_tube_service::yt_dlp;
+
+    fn throttle(config: &str, clock: &FakeClock) -> Throttle {
+        Throttle::new(ron::from_str(config).unwrap(), clock.clone())
+    }
+
+    fn transient_error() -> VideoSourceError {
+        VideoSourceError::ResponseParseFailed(io::Error::other("connection reset"))
+    }
+
+    fn rate_limited() -> VideoSourceError {
+        VideoSourceError::YTDLPError(yt_dlp::Error::RateLimited("HTTP Error 429".to_string()))
+    }
+
+    fn seconds(seconds: &[u64]) -> Vec<Duration> {
+        seconds.iter().map(|seconds| Duration::from_secs(*seconds)).collect()
+    }
+
+    #[test]
+    fn transient_errors_are_retried_with_backoff() {
+        let clock = FakeClock::new();
+        let throttle = throttle("(retry_attempts: 3, retry_base_delay: 2, retry_jitter: 0.0)", &clock);
+        let mut calls = 0;
+
+        let result = throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || {
+            calls += 1;
+            if calls < 3 { Err(transient_error()) } else { Ok(calls) }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(clock.sleeps(), seconds(&[2, 4]));
+    }
+
+    #[test]
+    fn retries_stop_after_the_attempts() {
+        let clock = FakeClock::new();
+        let throttle = throttle("(retry_attempts: 2, retry_base_delay: 1, retry_jitter: 0.0)", &clock);
+        let mut calls = 0;
+
+        let result = throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || -> Result<()> {
+            calls += 1;
+            Err(transient_error())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+        assert_eq!(clock.sleeps(), seconds(&[1, 2]));
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let clock = FakeClock::new();
+        let throttle = throttle("(retry_attempts: 2)", &clock);
+        let mut calls = 0;
+
+        let result = throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || -> Result<()> {
+            calls += 1;
+            Err(VideoSourceError::UnexpectedResponse("not a playlist".to_string()))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert!(clock.sleeps().is_empty());
+    }
+
+    #[test]
+    fn requests_wait_for_their_turn() {
+        let clock = FakeClock::new();
+        let throttle = throttle("(requests_per_minute: 2)", &clock);
+
+        for _ in 0..3 {
+            throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || Ok(())).unwrap();
+        }
+        assert_eq!(clock.sleeps(), seconds(&[60]));
+
+        // the first two requests of the next minute are sent right away
+        clock.advance(Duration::from_secs(61));
+        throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || Ok(())).unwrap();
+        assert_eq!(clock.sleeps(), seconds(&[60]));
+    }
+
+    #[test]
+    fn requests_are_paused_after_rate_limits() {
+        let clock = FakeClock::new();
+        let throttle = throttle("(retry_attempts: 0, pause_after_rate_limits: 2, pause_minutes: 10)", &clock);
+        let mut calls = 0;
+        let mut request = || throttle.request("UUaaaaaaaaaaaaaaaaaaaaaa", || -> Result<()> {
+            calls += 1;
+            Err(rate_limited())
+        });
+
+        assert!(request().is_err());
+        assert!(request().is_err());
+        assert!(matches!(request(), Err(VideoSourceError::Paused(600))));
+
+        clock.advance(Duration::from_secs(600));
+        assert!(matches!(request(), Err(VideoSourceError::YTDLPError(_))));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn every_request_of_a_fallback_counts() {
+        let clock = FakeClock::new();
+        let throttle = Arc::new(throttle("(requests_per_minute: 1)", &clock));
+        let (instance, yt_dlp) = (FixtureSource::default(), FixtureSource::from_yt_dlp_output(include_str!("../../tests/fixtures/yt_dlp_playlists.jsonl")));
+        instance.set_failing("UUaaaaaaaaaaaaaaaaaaaaaa");
+        let source = FallbackSource::new(ThrottledSource::new(instance, throttle.clone()), ThrottledSource::new(yt_dlp, throttle));
+
+        assert_eq!(source.retrieve_latest_items("UUaaaaaaaaaaaaaaaaaaaaaa", 2).unwrap().len(), 2);
+        assert_eq!(clock.sleeps(), seconds(&[60]));
+    }
+}
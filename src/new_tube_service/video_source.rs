@@ -0,0 +1,150 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use error_generator::error;
+
+use crate::config::VideoSourceConfig;
+use crate::new_tube_service::database::{DBError, Database};
+use crate::new_tube_service::invidious::InvidiousSource;
+use crate::new_tube_service::piped::PipedSource;
+use crate::new_tube_service::throttle::{Throttle, ThrottledSource};
+use crate::new_tube_service::youtube_feed::YouTubeFeedSource;
+use crate::new_tube_service::yt_dlp;
+use crate::new_tube_service::yt_dlp::{YTDLPItem, YTDLPSource};
+
+pub type Result<T> = std::result::Result<T, VideoSourceError>;
+
+/// How long a http request of a video source might take before it is aborted
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Something that can retrieve the latest items of a YouTube playlist.
+///
+/// yt-dlp is the default implementation, but the service does not care where the items come from.
+/// The service calls it from multiple threads at once, so implementations must be thread safe.
+pub trait VideoSource: Send + Sync {
+    /// Retrieve up to `amount` of the latest items from the given playlist, starting with the newest one.
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>>;
+}
+
+/// Create the video source defined in the config. Instances of Invidious and Piped fall back to yt-dlp.
+/// Every request of the source goes through the throttle and yt-dlp gets killed after the timeout.
+pub fn create_video_source(config: &VideoSourceConfig, throttle: &Arc<Throttle>, yt_dlp_timeout: Duration) -> std::result::Result<Box<dyn VideoSource>, DBError> {
+    let yt_dlp = ThrottledSource::new(YTDLPSource::new(yt_dlp_timeout), throttle.clone());
+
+    Ok(match config {
+        VideoSourceConfig::YtDlp => Box::new(yt_dlp),
+        VideoSourceConfig::Invidious(instance) => Box::new(FallbackSource::new(ThrottledSource::new(InvidiousSource::new(instance), throttle.clone()), yt_dlp)),
+        VideoSourceConfig::Piped(api_url) => Box::new(FallbackSource::new(ThrottledSource::new(PipedSource::new(api_url), throttle.clone()), yt_dlp)),
+        // the feed source caches the items in its own connection, as it is used from multiple threads
+        VideoSourceConfig::YouTubeFeed => Box::new(YouTubeFeedSource::new(Database::open()?, throttle.clone(), yt_dlp)),
+    })
+}
+
+/// A video source which asks another source if the first one fails
+pub struct FallbackSource {
+    source: Box<dyn VideoSource>,
+    fallback: Box<dyn VideoSource>,
+}
+
+impl FallbackSource {
+    pub fn new(source: impl VideoSource + 'static, fallback: impl VideoSource + 'static) -> Self {
+        FallbackSource {
+            source: Box::new(source),
+            fallback: Box::new(fallback),
+        }
+    }
+}
+
+impl VideoSource for FallbackSource {
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>> {
+        self.source
+            .retrieve_latest_items(playlist_id, amount)
+            .or_else(|err| {
+                println!("Failed to fetch playlist {playlist_id}, trying the fallback. Reason: {err}");
+                self.fallback.retrieve_latest_items(playlist_id, amount)
+            })
+    }
+}
+
+/// The agent used by all http based video sources
+pub fn http_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+}
+
+impl VideoSourceError {
+    /// Check if the error might go away by trying again later
+    pub fn is_transient(&self) -> bool {
+        match self {
+            VideoSourceError::YTDLPError(error) => error.is_transient(),
+            VideoSourceError::RequestFailed(error) => match error.as_ref() {
+                ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+                ureq::Error::Transport(_) => true,
+            },
+            VideoSourceError::ResponseParseFailed(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            VideoSourceError::YTDLPError(error) => matches!(error, yt_dlp::Error::RateLimited(_)),
+            VideoSourceError::RequestFailed(error) => matches!(error.as_ref(), ureq::Error::Status(429, _)),
+            _ => false
+        }
+    }
+}
+
+#[error]
+pub enum VideoSourceError {
+    #[error(message = "{_0}", impl_from)]
+    YTDLPError(yt_dlp::Error),
+    #[error(message = "HTTP request failed: {_0}", impl_from)]
+    RequestFailed(Box<ureq::Error>),
+    #[error(message = "Failed to read the http response: {_0}", impl_from)]
+    ResponseParseFailed(io::Error),
+    #[error(message = "Unexpected response of the instance: {_0}")]
+    UnexpectedResponse(String),
+    #[error(message = "{_0}", impl_from)]
+    Database(DBError),
+    #[error(message = "Fetching is paused for {_0} more seconds, as YouTube rate limited too many requests")]
+    Paused(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockServer;
+    use crate::new_tube_service::fixture_source::FixtureSource;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/yt_dlp_playlists.jsonl");
+    const WOOD_WORKS: &str = "UUaaaaaaaaaaaaaaaaaaaaaa";
+
+    fn ids(items: &[YTDLPItem]) -> Vec<&str> {
+        items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn fallback_is_not_asked_if_the_source_works() {
+        let instance = MockServer::respond_with(200, include_str!("../../tests/fixtures/invidious_playlist.json"));
+        let fallback = FixtureSource::from_yt_dlp_output(FIXTURE);
+        let source = FallbackSource::new(InvidiousSource::new(&instance.url), fallback);
+
+        assert_eq!(ids(&source.retrieve_latest_items(WOOD_WORKS, 1).unwrap()), ["h7Ur5tYeW3q"]);
+    }
+
+    #[test]
+    fn fallback_is_asked_if_the_source_fails() {
+        let instance = MockServer::respond_with(502, "");
+        let fallback = FixtureSource::from_yt_dlp_output(FIXTURE);
+        let source = FallbackSource::new(PipedSource::new(&instance.url), fallback.clone());
+
+        assert_eq!(ids(&source.retrieve_latest_items(WOOD_WORKS, 10).unwrap()), ids(&fallback.items(WOOD_WORKS)));
+        assert_eq!(instance.requests().len(), 1);
+
+        fallback.set_failing(WOOD_WORKS);
+        assert!(source.retrieve_latest_items(WOOD_WORKS, 10).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::{Arc, Mutex};
+
+use crate::new_tube_service::database::Database;
+use crate::new_tube_service::throttle::Throttle;
+use crate::new_tube_service::video_source::{http_agent, Result, VideoSource, VideoSourceError};
+use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+/// Where YouTube serves the feeds of the playlists
+const FEED_URL: &str = "https://www.youtube.com/feeds/videos.xml";
+
+/// A video source which polls the Atom feed YouTube provides for every playlist. The feed is much cheaper
+/// to request than a yt-dlp run, but lacks details like the duration of the videos.
+///
+/// The feed is only used to detect changes. The items are retrieved from the details source when the feed
+/// shows a new entry and are cached until then. The ETag and Last-Modified headers of the feed are cached too,
+/// so YouTube can answer with 304 Not Modified if nothing changed. If the feed cannot be requested,
+/// the items are retrieved from the details source as well.
+///
+/// Livestreams change without showing up in the feed, so playlists with running or upcoming
+/// livestreams are always fetched from the details source.
+pub struct YouTubeFeedSource {
+    agent: ureq::Agent,
+    /// The url of the feeds, without the playlist id
+    feed_url: String,
+    /// Where the feed headers and the latest items are cached
+    database: Mutex<Database>,
+    /// The feed requests go through it, the details source has its own
+    throttle: Arc<Throttle>,
+    /// Retrieves the items when the feed changed
+    details_source: Box<dyn VideoSource>,
+}
+
+/// What was cached for a playlist after its feed was requested
+pub struct FeedCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// The latest items of the playlist, newest first
+    pub items: Vec<YTDLPItem>,
+}
+
+impl YouTubeFeedSource {
+    pub fn new(database: Database, throttle: Arc<Throttle>, details_source: impl VideoSource + 'static) -> Self {
+        Self::with_feed_url(FEED_URL, database, throttle, details_source)
+    }
+
+    /// Create the source with another server than YouTube, which serves the feeds at `<feed_url>?playlist_id=<PLAYLIST_ID>`
+    pub fn with_feed_url(feed_url: &str, database: Database, throttle: Arc<Throttle>, details_source: impl VideoSource + 'static) -> Self {
+        YouTubeFeedSource {
+            agent: http_agent(),
+            feed_url: feed_url.to_string(),
+            database: Mutex::new(database),
+            throttle,
+            details_source: Box::new(details_source),
+        }
+    }
+
+    fn database(&self) -> std::sync::MutexGuard<'_, Database> {
+        self.database.lock().expect("the database should not be poisoned")
+    }
+
+    /// Check if the cached items can be returned instead of asking the details source.
+    /// The feed ids are None if the feed did not change since the last request, so the cache is still up to date.
+    fn cache_is_valid(cache: &FeedCache, feed_ids: Option<&[String]>, amount: usize) -> bool {
+        let has_livestreams = cache.items
+            .iter()
+            .any(|item| matches!(item.live_status.as_deref(), Some("is_live" | "is_upcoming")));
+
+        if has_livestreams {
+            return false;
+        }
+
+        match feed_ids {
+            None => true,
+            Some(ids) => ids.iter().take(amount).eq(cache.items.iter().take(amount).map(|item| &item.id))
+        }
+    }
+}
+
+impl VideoSource for YouTubeFeedSource {
+    // Example: GET https://www.youtube.com/feeds/videos.xml?playlist_id=<PLAYLIST_ID>
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> Result<Vec<YTDLPItem>> {
+        let cache = self.database().get_feed_cache(playlist_id)?;
+        let mut request = self.agent.get(&format!("{}?playlist_id={playlist_id}", self.feed_url));
+
+        if let Some(etag) = cache.as_ref().and_then(|cache| cache.etag.as_deref()) {
+            request = request.set("If-None-Match", etag)
+        }
+
+        if let Some(last_modified) = cache.as_ref().and_then(|cache| cache.last_modified.as_deref()) {
+            request = request.set("If-Modified-Since", last_modified)
+        }
+
+        let response = match self.throttle.request(playlist_id, || request.clone().call().map_err(|err| VideoSourceError::from(Box::new(err)))) {
+            Ok(response) => response,
+            Err(err) => {
+                println!("Failed to request the feed of playlist {playlist_id}, asking the details source. Reason: {err}");
+                return self.details_source.retrieve_latest_items(playlist_id, amount);
+            }
+        };
+        // a 304 response does not have to repeat the headers
+        let etag = response
+            .header("ETag")
+            .map(str::to_string)
+            .or_else(|| cache.as_ref().and_then(|cache| cache.etag.clone()));
+        let last_modified = response
+            .header("Last-Modified")
+            .map(str::to_string)
+            .or_else(|| cache.as_ref().and_then(|cache| cache.last_modified.clone()));
+        let feed_ids = match response.status() {
+            304 => None,
+            _ => Some(parse_video_ids(&response.into_string()?))
+        };
+
+        let items = match cache {
+            Some(cache) if Self::cache_is_valid(&cache, feed_ids.as_deref(), amount) => cache.items.into_iter().take(amount).collect(),
+            _ => self.details_source.retrieve_latest_items(playlist_id, amount)?
+        };
+
+        self.database().set_feed_cache(playlist_id, &FeedCache {
+            etag,
+            last_modified,
+            items: items.clone(),
+        })?;
+
+        Ok(items)
+    }
+}
+
+/// Get the ids of the videos in the feed, in the order of the entries.
+///
+/// Every entry of the feed contains its video id like <yt:videoId>ID</yt:videoId>.
+/// The feed is never used for anything else, so no full XML parser is needed.
+pub fn parse_video_ids(feed: &str) -> Vec<String> {
+    feed
+        .split("<entry>")
+        .skip(1)
+        .filter_map(|entry| {
+            let start = entry.find("<yt:videoId>")? + "<yt:videoId>".len();
+            let end = start + entry[start..].find("</yt:videoId>")?;
+            Some(entry[start..end].trim().to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+    use crate::new_tube_service::fixture_source::FixtureSource;
+    use crate::new_tube_service::throttle::FakeClock;
+
+    const FEED: &str = include_str!("../../tests/fixtures/youtube_feed.xml");
+    const FIXTURE: &str = include_str!("../../tests/fixtures/yt_dlp_playlists.jsonl");
+    const WOOD_WORKS: &str = "UUaaaaaaaaaaaaaaaaaaaaaa";
+
+    /// A feed server whose answer can be changed by the test
+    fn feed_server(response: MockResponse) -> (MockServer, Arc<Mutex<MockResponse>>) {
+        let response = Arc::new(Mutex::new(response));
+        let server_response = response.clone();
+        (MockServer::start(move |_| server_response.lock().unwrap().clone()), response)
+    }
+
+    /// Failed feed requests are retried, but the fake clock does not wait for real
+    fn throttle() -> Arc<Throttle> {
+        Arc::new(Throttle::new(Default::default(), FakeClock::new()))
+    }
+
+    fn ids(items: &[YTDLPItem]) -> Vec<&str> {
+        items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_video_ids_of_a_saved_feed() {
+        assert_eq!(parse_video_ids(FEED), ["a5xvRqg9yDc", "b4PLqW0XkTs", "c3kDuf8sLwE"]);
+        assert!(parse_video_ids(&FEED.replace("<entry>", "<ignored>")).is_empty());
+    }
+
+    #[test]
+    fn details_are_only_retrieved_if_the_feed_changed() {
+        let (server, response) = feed_server(MockResponse::new(200, FEED).with_header("ETag", "\"v1\""));
+        let details = FixtureSource::from_yt_dlp_output(FIXTURE);
+        let source = YouTubeFeedSource::with_feed_url(&format!("{}/feeds/videos.xml", server.url), Database::open_in_memory().unwrap(), throttle(), details.clone());
+        let fixture_ids = ids(&details.items(WOOD_WORKS)).into_iter().map(str::to_string).collect::<Vec<_>>();
+
+        // nothing is cached yet
+        assert_eq!(ids(&source.retrieve_latest_items(WOOD_WORKS, 3).unwrap()), fixture_ids[..3]);
+        assert_eq!(server.requests()[0].url, format!("/feeds/videos.xml?playlist_id={WOOD_WORKS}"));
+
+        // the feed is not modified, so the cached items are returned
+        details.set_items(WOOD_WORKS, vec![]);
+        *response.lock().unwrap() = MockResponse::new(304, "");
+        assert_eq!(ids(&source.retrieve_latest_items(WOOD_WORKS, 3).unwrap()), fixture_ids[..3]);
+        assert_eq!(server.requests()[1].header("If-None-Match"), Some("\"v1\""));
+
+        // the feed has the same entries, so the cached items are returned too
+        *response.lock().unwrap() = MockResponse::new(200, FEED);
+        assert_eq!(ids(&source.retrieve_latest_items(WOOD_WORKS, 3).unwrap()), fixture_ids[..3]);
+
+        // a new entry in the feed asks the details source
+        let new_feed = FEED.replacen("<entry>", "<entry>\n  <yt:videoId>n0Ew7vIdEo1</yt:videoId>\n </entry>\n <entry>", 1);
+        *response.lock().unwrap() = MockResponse::new(200, &new_feed);
+        assert!(source.retrieve_latest_items(WOOD_WORKS, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn details_are_retrieved_if_the_feed_fails() {
+        let (server, response) = feed_server(MockResponse::new(404, "Not Found"));
+        let details = FixtureSource::from_yt_dlp_output(FIXTURE);
+        let source = YouTubeFeedSource::with_feed_url(&server.url, Database::open_in_memory().unwrap(), throttle(), details.clone());
+
+        assert_eq!(source.retrieve_latest_items(WOOD_WORKS, 5).unwrap().len(), 5);
+
+        *response.lock().unwrap() = MockResponse::new(503, "");
+        details.set_items(WOOD_WORKS, details.items(WOOD_WORKS)[1..].to_vec());
+        assert_eq!(source.retrieve_latest_items(WOOD_WORKS, 5).unwrap().len(), 4);
+
+        details.set_failing(WOOD_WORKS);
+        assert!(source.retrieve_latest_items(WOOD_WORKS, 5).is_err());
+    }
+}
@@ -1,22 +1,39 @@
-use std::process::{Command, Output};
+use std::io;
+use std::process::{Command, ExitStatus, Output, Stdio};
 use std::string::FromUtf8Error;
+use std::time::Duration;
 
 use error_generator::error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::new_tube_service::process::RunningProcess;
+use crate::new_tube_service::video_source;
+use crate::new_tube_service::video_source::VideoSource;
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// Use yt-dlp to retrieve the last 2 items from the given playlist id and parse them.
-pub fn retrieve_latest_items(playlist_id: &str) -> Result<YTDLPResponse> {
-    let output = execute_command(playlist_id)?;
-    parse_output_to_items(output)
+/// A video source which spawns a yt-dlp process for every request.
+pub struct YTDLPSource {
+    /// How long a yt-dlp process might run before it gets killed
+    timeout: Duration,
+}
+
+impl YTDLPSource {
+    pub fn new(timeout: Duration) -> Self {
+        YTDLPSource { timeout }
+    }
+}
+
+impl VideoSource for YTDLPSource {
+    fn retrieve_latest_items(&self, playlist_id: &str, amount: usize) -> video_source::Result<Vec<YTDLPItem>> {
+        let output = execute_command(playlist_id, amount, self.timeout)?;
+        Ok(parse_output_to_items(output)?)
+    }
 }
 
 // Example: yt-dlp https://www.youtube.com/watch?list=<PLAYLIST_ID> --skip-download --quiet --playlist-start 1 --playlist-end 3 --print-json --flat-playlist
-fn execute_command(playlist_id: &str) -> Result<Output> {
-    // TODO: There is an async process library, but it only works blocking on windows. Could be faster if run
-    //  on a penguin machine.
-    Ok(Command::new("yt-dlp")
+fn execute_command(playlist_id: &str, amount: usize, timeout: Duration) -> Result<Output> {
+    run(Command::new("yt-dlp")
         .arg(&format!(
             "https://www.youtube.com/watch?list={}",
             playlist_id
@@ -26,14 +43,59 @@ fn execute_command(playlist_id: &str) -> Result<Output> {
         .arg("--playlist-start")
         .arg("1")
         .arg("--playlist-end")
-        .arg("2")
+        .arg(amount.to_string())
         .arg("--print-json")
-        .arg("--flat-playlist")
-        .output()?)
+        .arg("--flat-playlist"), timeout)
 }
 
-fn parse_output_to_items(output: Output) -> Result<YTDLPResponse> {
+/// Run the yt-dlp command and wait for its output.
+/// If yt-dlp exits with an error, its stderr is checked for the reason.
+///
+/// yt-dlp and everything it starts (like ffmpeg) is killed if it takes longer than the timeout.
+pub fn run(command: &mut Command, timeout: Duration) -> Result<Output> {
+    let process = RunningProcess::spawn(command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped()))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Error::BinaryNotFound,
+            _ => Error::ProcessFailed(err)
+        })?;
+
+    let Some(output) = process.wait_with_timeout(timeout)? else {
+        return Err(Error::TimedOut(timeout.as_secs()));
+    };
+
+    match output.status.success() {
+        true => Ok(output),
+        false => Err(Error::from_failed_run(output.status, &String::from_utf8_lossy(&output.stderr)))
+    }
+}
+
+/// Use yt-dlp to find the id of the channel which owns the given URL.
+/// If the URL points to a playlist (like a channel tab), the channel of the playlist is returned,
+/// otherwise the channel which uploaded the video.
+pub fn retrieve_channel_id(url: &str, is_playlist: bool, timeout: Duration) -> Result<String> {
+    let output = run(Command::new("yt-dlp")
+        .arg(url)
+        .arg("--skip-download")
+        .arg("--quiet")
+        .arg("--flat-playlist")
+        .arg("--playlist-items")
+        .arg("1")
+        .arg("--print")
+        .arg(if is_playlist { "playlist:channel_id" } else { "channel_id" }), timeout)?;
+
     let output_string = String::from_utf8(output.stdout)?;
+    Ok(output_string.lines().next().unwrap_or_default().trim().to_string())
+}
+
+fn parse_output_to_items(output: Output) -> Result<Vec<YTDLPItem>> {
+    parse_items(&String::from_utf8(output.stdout)?)
+}
+
+/// Parse the output of yt-dlp, which prints one JSON object per item and line
+pub fn parse_items(output_string: &str) -> Result<Vec<YTDLPItem>> {
     let mut items = vec![];
 
     for s in output_string
@@ -44,30 +106,32 @@ fn parse_output_to_items(output: Output) -> Result<YTDLPResponse> {
         items.push(serde_json::from_str(s)?)
     }
 
-    if items.len() != 2 {
-        // The playlist needs at least 2 items. A latest one and the previous one
-        return Err(Error::WrongAmountReturned)
-    }
-
-    let previous_item = items.remove(1);
-    let latest_item = items.remove(0);
-
-    Ok(YTDLPResponse {
-        previous_item,
-        latest_item,
-    })
+    Ok(items)
 }
 
-/// The response from a call to yt_dlp to retrieve the latest 2 videos from a playlist.
+/// The latest 2 videos from a playlist.
 pub struct YTDLPResponse {
     /// The latest item from the playlist
     pub latest_item: YTDLPItem,
-    /// The item previous to the latest item in the playlist
-    pub previous_item: YTDLPItem
+    /// The item previous to the latest item in the playlist. None if the playlist has only one item
+    pub previous_item: Option<YTDLPItem>
+}
+
+impl YTDLPResponse {
+    /// Create the response from the items returned by a video source, newest first.
+    /// Returns None if the playlist is empty.
+    pub fn from_items(items: Vec<YTDLPItem>) -> Option<Self> {
+        let mut items = items.into_iter();
+
+        Some(YTDLPResponse {
+            latest_item: items.next()?,
+            previous_item: items.next(),
+        })
+    }
 }
 
 /// The relevant fields of a playlist item, returned from yt-dlp
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct YTDLPItem {
     /// The playlist id
     pub playlist_id: String,
@@ -80,6 +144,56 @@ pub struct YTDLPItem {
     pub duration: Option<f32>,
     /// The channel which uploaded the video
     pub channel: String,
+    /// If the video is or was a livestream. One of not_live, is_live, is_upcoming, was_live or post_live.
+    /// Might be None if yt-dlp does not know it
+    #[serde(default)]
+    pub live_status: Option<String>,
+    /// When an upcoming livestream or premiere starts, as a unix timestamp
+    #[serde(default)]
+    pub release_timestamp: Option<i64>,
+    /// The URL of the video. Shorts have URLs like https://www.youtube.com/shorts/<ID>
+    #[serde(default)]
+    pub url: Option<String>,
+    /// The width of the video in pixels, if yt-dlp knows it
+    #[serde(default)]
+    pub width: Option<u32>,
+    /// The height of the video in pixels, if yt-dlp knows it
+    #[serde(default)]
+    pub height: Option<u32>,
+}
+
+impl Error {
+    /// Find out why yt-dlp failed, using the messages it writes to stderr.
+    /// The error contains the last error message of yt-dlp, or its last line of output if there is none.
+    pub fn from_failed_run(status: ExitStatus, stderr: &str) -> Self {
+        let contains = |patterns: &[&str]| patterns.iter().any(|pattern| stderr.contains(pattern));
+        let message = stderr
+            .lines()
+            .rev()
+            .find(|line| line.starts_with("ERROR:"))
+            .or_else(|| stderr.lines().rev().find(|line| !line.trim().is_empty()))
+            .unwrap_or_default()
+            .trim_start_matches("ERROR:")
+            .trim()
+            .to_string();
+
+        if contains(&["HTTP Error 429", "Too Many Requests"]) {
+            Error::RateLimited(message)
+        } else if contains(&["Sign in to confirm", "Please sign in", "members-only", "requires authentication"]) {
+            Error::SignInRequired(message)
+        } else if contains(&["does not exist", "This playlist is private", "This channel is not available", "Video unavailable", "HTTP Error 404"]) {
+            Error::PlaylistUnavailable(message)
+        } else if contains(&["Unable to extract", "please report this issue", "Confirm you are on the latest version", "nsig extraction failed"]) {
+            Error::ExtractorOutdated(message)
+        } else {
+            Error::Failed(status, message)
+        }
+    }
+
+    /// Check if the error might go away by trying again later
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::ProcessFailed(_) | Error::RateLimited(_) | Error::Failed(_, _))
+    }
 }
 
 #[error]
@@ -89,6 +203,20 @@ pub enum Error {
         impl_from
     )]
     ProcessFailed(std::io::Error),
+    #[error(message = "yt-dlp was not found. Make sure it is installed and in the PATH")]
+    BinaryNotFound,
+    #[error(message = "The playlist is unavailable, it might have been deleted or made private: {_0}")]
+    PlaylistUnavailable(String),
+    #[error(message = "YouTube is rate limiting the requests (HTTP 429): {_0}")]
+    RateLimited(String),
+    #[error(message = "YouTube requires signing in: {_0}")]
+    SignInRequired(String),
+    #[error(message = "yt-dlp failed to extract the data and is probably outdated, try to update it: {_0}")]
+    ExtractorOutdated(String),
+    #[error(message = "yt-dlp did not finish within {_0} seconds and was killed")]
+    TimedOut(u64),
+    #[error(message = "yt-dlp exited with {_0}: {_1}")]
+    Failed(ExitStatus, String),
     #[error(
         message = "Failed to parse the process output to items. Reason: {_0}",
         impl_from
@@ -99,8 +227,4 @@ pub enum Error {
         impl_from
     )]
     CommandOutputParseFailed(FromUtf8Error),
-    #[error(
-        message = "yt_dlp did not return exactly 2 items, which is the expected amount"
-    )]
-    WrongAmountReturned
 }
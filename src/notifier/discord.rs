@@ -0,0 +1,43 @@
+use serde_json::json;
+
+use crate::link::LinkStyle;
+use crate::notifier::{http_agent, video_message, Notifier, Result};
+use crate::playlist_item::PlaylistItem;
+
+/// The maximum length of a discord message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Sends the notifications to a discord channel using a webhook
+pub struct DiscordNotifier {
+    agent: ureq::Agent,
+    webhook_url: String,
+}
+
+impl DiscordNotifier {
+    pub fn new(webhook_url: &str) -> Self {
+        DiscordNotifier {
+            agent: http_agent(),
+            webhook_url: webhook_url.to_string(),
+        }
+    }
+
+    /// Send a message to the channel. Messages which are too long for discord are cut off.
+    fn send_message(&self, message: &str) -> Result<()> {
+        let content = message.chars().take(MAX_MESSAGE_LENGTH).collect::<String>();
+        self.agent
+            .post(&self.webhook_url)
+            .send_json(json!({ "content": content }))
+            .map_err(Box::new)?;
+        Ok(())
+    }
+}
+
+impl Notifier for DiscordNotifier {
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()> {
+        self.send_message(&video_message(video, links))
+    }
+
+    fn notify_error(&self, message: &str) -> Result<()> {
+        self.send_message(message)
+    }
+}
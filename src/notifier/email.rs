@@ -0,0 +1,70 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::config::SmtpEncryption;
+use crate::link::LinkStyle;
+use crate::notifier::{video_message, video_title, Notifier, Result};
+use crate::playlist_item::PlaylistItem;
+
+/// Sends the notifications as emails using a SMTP server
+pub struct EmailNotifier {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    /// Create the notifier. If no port is given, the default port of the encryption is used.
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        encryption: SmtpEncryption,
+        credentials: Option<(String, String)>,
+        from: &str,
+        to: &[String],
+    ) -> Result<Self> {
+        let mut builder = match encryption {
+            SmtpEncryption::Plain => SmtpTransport::builder_dangerous(host),
+            SmtpEncryption::StartTls => SmtpTransport::starttls_relay(host)?,
+            SmtpEncryption::Tls => SmtpTransport::relay(host)?,
+        };
+
+        if let Some(port) = port {
+            builder = builder.port(port)
+        }
+
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password))
+        }
+
+        Ok(EmailNotifier {
+            transport: builder.build(),
+            from: from.parse()?,
+            to: to.iter().map(|address| address.parse()).collect::<std::result::Result<_, _>>()?,
+        })
+    }
+
+    fn send_mail(&self, subject: &str, body: String) -> Result<()> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(subject);
+
+        for to in &self.to {
+            builder = builder.to(to.clone())
+        }
+
+        self.transport.send(&builder.body(body)?)?;
+        Ok(())
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()> {
+        self.send_mail(&video_title(video), video_message(video, links))
+    }
+
+    fn notify_error(&self, message: &str) -> Result<()> {
+        self.send_mail("new_tube error", message.to_string())
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::link::LinkStyle;
+use crate::notifier::{http_agent, video_message, Notifier, Result};
+use crate::playlist_item::PlaylistItem;
+
+/// Sends the notifications to a matrix room, using the client-server api of a homeserver
+pub struct MatrixNotifier {
+    agent: ureq::Agent,
+    homeserver: String,
+    access_token: String,
+    room_id: String,
+    /// Counts the sent messages, to create unique transaction ids
+    sent_messages: AtomicU64,
+}
+
+impl MatrixNotifier {
+    pub fn new(homeserver: &str, access_token: &str, room_id: &str) -> Self {
+        MatrixNotifier {
+            agent: http_agent(),
+            homeserver: homeserver.trim_end_matches('/').to_string(),
+            access_token: access_token.to_string(),
+            room_id: room_id.to_string(),
+            sent_messages: AtomicU64::new(0),
+        }
+    }
+
+    /// Send a text message to the room (https://spec.matrix.org/latest/client-server-api/#put_matrixclientv3roomsroomidsendeventtypetxnid)
+    fn send_message(&self, message: &str) -> Result<()> {
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver,
+            encode_path_segment(&self.room_id),
+            self.next_transaction_id()
+        );
+
+        self.agent
+            .put(&url)
+            .set("Authorization", &format!("Bearer {}", self.access_token))
+            .send_json(json!({
+                "msgtype": "m.text",
+                "body": message,
+            }))
+            .map_err(Box::new)?;
+        Ok(())
+    }
+
+    /// The homeserver ignores messages with a transaction id it already knows, so every message needs a new one.
+    /// The start time is included, so the ids stay unique after a restart.
+    fn next_transaction_id(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("the system time should be after the unix epoch")
+            .as_millis();
+        let count = self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        format!("new_tube_{now}_{count}")
+    }
+}
+
+/// Percent encode everything except unreserved characters, as room ids like '!abc:example.org' contain reserved ones
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}")
+        })
+        .collect()
+}
+
+impl Notifier for MatrixNotifier {
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()> {
+        self.send_message(&video_message(video, links))
+    }
+
+    fn notify_error(&self, message: &str) -> Result<()> {
+        self.send_message(message)
+    }
+}
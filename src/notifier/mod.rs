@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use error_generator::error;
+
+use crate::config::NotifierConfig;
+use crate::environment::get_telegram_api_key;
+use crate::link::LinkStyle;
+use crate::new_tube_service::hook::HookFailure;
+use crate::new_tube_service::{FetchFailure, FetchResult};
+use crate::notifier::discord::DiscordNotifier;
+use crate::notifier::email::EmailNotifier;
+use crate::notifier::matrix::MatrixNotifier;
+use crate::notifier::ntfy::NtfyNotifier;
+use crate::notifier::telegram::TelegramNotifier;
+use crate::notifier::webhook::WebhookNotifier;
+use crate::playlist_item::PlaylistItem;
+
+pub mod discord;
+pub mod email;
+pub mod matrix;
+pub mod ntfy;
+pub mod telegram;
+pub mod webhook;
+
+type Result<T> = std::result::Result<T, NotifierError>;
+
+/// How long a http request of a notifier might take before it is aborted
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Something which delivers new videos and errors to the user.
+pub trait Notifier: Send + Sync {
+    /// Tell the user about a new video, with links to it in the given styles
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()>;
+
+    /// Tell the user about a problem, like playlists which could not be fetched
+    fn notify_error(&self, message: &str) -> Result<()>;
+}
+
+/// Create the notifiers defined in the config
+pub fn create_notifiers(configs: &[NotifierConfig]) -> Result<Vec<Box<dyn Notifier>>> {
+    configs
+        .iter()
+        .map(|config| Ok(match config {
+            NotifierConfig::Telegram { chat_id, api_url } => Box::new(TelegramNotifier::with_api_url(
+                api_url.as_deref(),
+                &get_telegram_api_key(),
+                *chat_id,
+            )) as Box<dyn Notifier>,
+            NotifierConfig::Webhook { url } => Box::new(WebhookNotifier::new(url)),
+            NotifierConfig::Ntfy { server, topic, token } => Box::new(NtfyNotifier::new(server, topic, token.clone())),
+            NotifierConfig::Discord { webhook_url } => Box::new(DiscordNotifier::new(webhook_url)),
+            NotifierConfig::Matrix { homeserver, access_token, room_id } => Box::new(MatrixNotifier::new(homeserver, access_token, room_id)),
+            NotifierConfig::Email { host, port, encryption, username, password, from, to } => Box::new(EmailNotifier::new(
+                host,
+                *port,
+                *encryption,
+                credentials(username, password)?,
+                from,
+                to,
+            )?),
+        }))
+        .collect()
+}
+
+/// The login of a SMTP server. Setting only the username or only the password is a mistake in the config
+fn credentials(username: &Option<String>, password: &Option<String>) -> Result<Option<(String, String)>> {
+    match (username, password) {
+        (Some(username), Some(password)) => Ok(Some((username.clone(), password.clone()))),
+        (None, None) => Ok(None),
+        _ => Err(NotifierError::IncompleteCredentials)
+    }
+}
+
+/// Send the new videos, failures and disabled playlists of a fetch cycle to all notifiers.
+/// Errors while notifying are only logged, so one broken notifier does not affect the others.
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], links: &[LinkStyle], result: &FetchResult) {
+    for notifier in notifiers {
+        for video in &result.new_videos {
+            log_error(notifier.notify_video(video, links))
+        }
+
+        notify_errors(notifier.as_ref(), result)
+    }
+}
+
+/// Send the failures, disabled and skipped playlists and failed hooks of a fetch cycle to the notifier.
+/// Failed hooks are only sent if the user wants to be notified about them.
+pub fn notify_errors(notifier: &dyn Notifier, result: &FetchResult) {
+    if !result.failures.is_empty() {
+        log_error(notifier.notify_error(&failures_message(&result.failures)))
+    }
+
+    if !result.skipped.is_empty() {
+        log_error(notifier.notify_error(&skipped_message(&result.skipped)))
+    }
+
+    for playlist_id in &result.disabled {
+        log_error(notifier.notify_error(&disabled_message(playlist_id)))
+    }
+
+    for failure in result.hook_failures.iter().filter(|failure| failure.notify) {
+        log_error(notifier.notify_error(&hook_failure_message(failure)))
+    }
+}
+
+fn log_error(result: Result<()>) {
+    if let Err(err) = result {
+        println!("failed to send notification due to error: {err}")
+    }
+}
+
+/// The text of a notification about a new video, with one link per style
+pub fn video_message(video: &PlaylistItem, links: &[LinkStyle]) -> String {
+    let message = format!(
+        "{}\n{}\n{}\n{}",
+        video.uploader,
+        video.title,
+        video.formatted_duration(),
+        video.links(links).join("\n")
+    );
+
+    match video.headline() {
+        Some(headline) => format!("{headline}\n{message}"),
+        None => message
+    }
+}
+
+/// The title of a notification about a new video, for notifiers which support one
+pub fn video_title(video: &PlaylistItem) -> String {
+    match video.headline() {
+        Some(headline) => format!("{headline}: {} - {}", video.uploader, video.title),
+        None => format!("{} - {}", video.uploader, video.title)
+    }
+}
+
+pub fn failures_message(failures: &[FetchFailure]) -> String {
+    let failures = failures
+        .iter()
+        .map(|failure| format!("{}: {}", failure.playlist_id, failure.error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("Failed to fetch some playlists:\n{failures}")
+}
+
+pub fn disabled_message(playlist_id: &str) -> String {
+    format!("Playlist {playlist_id} failed too often and was disabled. Run 'new_tube enable {playlist_id}' to enable it again.")
+}
+
+pub fn skipped_message(playlist_ids: &[String]) -> String {
+    format!("YouTube rate limited too many requests, so fetching is paused. {} playlists were skipped.", playlist_ids.len())
+}
+
+pub fn hook_failure_message(failure: &HookFailure) -> String {
+    format!("Hook '{}' failed for video {}: {}", failure.command, failure.video_id, failure.error)
+}
+
+/// The agent used by all http based notifiers
+fn http_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+}
+
+#[error]
+pub enum NotifierError {
+    #[error(message = "Telegram request failed: {_0}", impl_from)]
+    TelegramRequestFailed(frankenstein::Error),
+    #[error(message = "HTTP request failed: {_0}", impl_from)]
+    HttpRequestFailed(Box<ureq::Error>),
+    #[error(message = "Invalid email address: {_0}", impl_from)]
+    InvalidEmailAddress(lettre::address::AddressError),
+    #[error(message = "Failed to create email: {_0}", impl_from)]
+    EmailCreationFailed(lettre::error::Error),
+    #[error(message = "Failed to send email: {_0}", impl_from)]
+    SmtpFailed(lettre::transport::smtp::Error),
+    #[error(message = "The email notifier needs both a username and a password, or neither of them")]
+    IncompleteCredentials,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::config::SmtpEncryption;
+    use crate::mock_server::MockServer;
+    use crate::new_tube_service::yt_dlp::YTDLPItem;
+
+    fn video() -> PlaylistItem {
+        let item = serde_json::from_value::<YTDLPItem>(json!({
+            "playlist_id": "UUaaaaaaaaaaaaaaaaaaaaaa",
+            "id": "a5xvRqg9yDc",
+            "title": "Building a workbench",
+            "duration": 754.0,
+            "channel": "Wood Works",
+        })).unwrap();
+        PlaylistItem::new(item, String::new())
+    }
+
+    fn body_json(server: &MockServer) -> Vec<Value> {
+        server.requests().iter().map(|request| serde_json::from_str(&request.body).unwrap()).collect()
+    }
+
+    #[test]
+    fn webhook_posts_videos_and_errors_as_json() {
+        let server = MockServer::respond_with(200, "");
+        let notifier = WebhookNotifier::new(&format!("{}/hook", server.url));
+
+        notifier.notify_video(&video(), &[LinkStyle::YouTube]).unwrap();
+        notifier.notify_error("something broke").unwrap();
+
+        let requests = server.requests();
+        assert_eq!((requests[0].method.as_str(), requests[0].url.as_str()), ("POST", "/hook"));
+        let bodies = body_json(&server);
+        assert_eq!(bodies[0]["type"], "video");
+        assert_eq!(bodies[0]["video"]["video_id"], "a5xvRqg9yDc");
+        assert_eq!(bodies[0]["link"], "https://www.youtube.com/watch?v=a5xvRqg9yDc");
+        assert_eq!(bodies[1], json!({"type": "error", "message": "something broke"}));
+    }
+
+    #[test]
+    fn ntfy_publishes_to_the_topic_with_the_token() {
+        let server = MockServer::respond_with(200, "{}");
+        let notifier = NtfyNotifier::new(&format!("{}/", server.url), "videos", Some("tk_secret".to_string()));
+
+        notifier.notify_video(&video(), &[LinkStyle::YouTube]).unwrap();
+
+        let request = &server.requests()[0];
+        assert!(request.url.starts_with("/videos?"));
+        assert!(request.url.contains("title=Wood+Works+-+Building+a+workbench"));
+        assert!(request.url.contains("click=https%3A%2F%2Fwww.youtube.com%2Fwatch%3Fv%3Da5xvRqg9yDc"));
+        assert_eq!(request.header("Authorization"), Some("Bearer tk_secret"));
+        assert!(request.body.contains("https://www.youtube.com/watch?v=a5xvRqg9yDc"));
+    }
+
+    #[test]
+    fn discord_cuts_off_long_messages() {
+        let server = MockServer::respond_with(204, "");
+        let notifier = DiscordNotifier::new(&server.url);
+
+        notifier.notify_error(&"x".repeat(3000)).unwrap();
+
+        assert_eq!(body_json(&server)[0]["content"].as_str().unwrap().len(), 2000);
+    }
+
+    #[test]
+    fn matrix_encodes_the_room_id() {
+        let server = MockServer::respond_with(200, r#"{"event_id": "$1"}"#);
+        let notifier = MatrixNotifier::new(&server.url, "syt_token", "!room/id:example.org");
+
+        notifier.notify_error("something broke").unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "PUT");
+        assert!(request.url.starts_with("/_matrix/client/v3/rooms/%21room%2Fid%3Aexample.org/send/m.room.message/new_tube_"));
+        assert_eq!(request.header("Authorization"), Some("Bearer syt_token"));
+        assert_eq!(body_json(&server)[0], json!({"msgtype": "m.text", "body": "something broke"}));
+    }
+
+    #[test]
+    fn http_errors_are_reported() {
+        let server = MockServer::respond_with(500, "internal error");
+
+        assert!(matches!(WebhookNotifier::new(&server.url).notify_error("message"), Err(NotifierError::HttpRequestFailed(_))));
+    }
+
+    /// Start a SMTP server which accepts every mail and records the commands and mails it received
+    fn smtp_server() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(vec![]));
+
+        let thread_received = received.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut in_data = false;
+                write!(stream, "220 localhost ESMTP\r\n").unwrap();
+
+                for line in (&mut reader).lines() {
+                    let line = line.unwrap();
+                    thread_received.lock().unwrap().push(line.clone());
+
+                    let reply = match line.split(' ').next().unwrap_or_default().to_uppercase().as_str() {
+                        _ if in_data && line == "." => {
+                            in_data = false;
+                            "250 OK"
+                        }
+                        _ if in_data => continue,
+                        "EHLO" => "250-localhost\r\n250 AUTH PLAIN LOGIN",
+                        "AUTH" => "235 Authentication successful",
+                        "DATA" => {
+                            in_data = true;
+                            "354 End data with <CR><LF>.<CR><LF>"
+                        }
+                        "QUIT" => {
+                            write!(stream, "221 Bye\r\n").unwrap();
+                            break;
+                        }
+                        _ => "250 OK"
+                    };
+                    write!(stream, "{reply}\r\n").unwrap();
+                }
+            }
+        });
+
+        (port, received)
+    }
+
+    #[test]
+    fn email_is_sent_with_the_login() {
+        let (port, received) = smtp_server();
+        let notifier = EmailNotifier::new(
+            "127.0.0.1",
+            Some(port),
+            SmtpEncryption::Plain,
+            Some(("user".to_string(), "pass".to_string())),
+            "new_tube@example.org",
+            &["me@example.org".to_string()],
+        ).unwrap();
+
+        notifier.notify_video(&video(), &[LinkStyle::YouTube]).unwrap();
+
+        let received = received.lock().unwrap();
+        // "\0user\0pass" in base64
+        assert!(received.iter().any(|line| line.starts_with("AUTH PLAIN AHVzZXIAcGFzcw==")));
+        assert!(received.contains(&"MAIL FROM:<new_tube@example.org>".to_string()));
+        assert!(received.contains(&"RCPT TO:<me@example.org>".to_string()));
+        assert!(received.contains(&"Subject: Wood Works - Building a workbench".to_string()));
+        assert!(received.contains(&"https://www.youtube.com/watch?v=a5xvRqg9yDc".to_string()));
+    }
+
+    #[test]
+    fn email_needs_both_username_and_password() {
+        let config = |username: Option<&str>, password: Option<&str>| NotifierConfig::Email {
+            host: "localhost".to_string(),
+            port: None,
+            encryption: SmtpEncryption::Plain,
+            username: username.map(str::to_string),
+            password: password.map(str::to_string),
+            from: "new_tube@example.org".to_string(),
+            to: vec!["me@example.org".to_string()],
+        };
+
+        assert!(matches!(create_notifiers(&[config(Some("user"), None)]), Err(NotifierError::IncompleteCredentials)));
+        assert!(matches!(create_notifiers(&[config(None, Some("pass"))]), Err(NotifierError::IncompleteCredentials)));
+        assert!(create_notifiers(&[config(Some("user"), Some("pass"))]).is_ok());
+        assert!(create_notifiers(&[config(None, None)]).is_ok());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::link::LinkStyle;
+use crate::notifier::{http_agent, video_title, Notifier, Result};
+use crate::playlist_item::PlaylistItem;
+
+/// Publishes the notifications to a topic of a ntfy server (https://ntfy.sh)
+pub struct NtfyNotifier {
+    agent: ureq::Agent,
+    topic_url: String,
+    token: Option<String>,
+}
+
+impl NtfyNotifier {
+    pub fn new(server: &str, topic: &str, token: Option<String>) -> Self {
+        NtfyNotifier {
+            agent: http_agent(),
+            topic_url: format!("{}/{topic}", server.trim_end_matches('/')),
+            token,
+        }
+    }
+
+    /// Publish a message. The title and click url are passed as query parameters,
+    /// as headers would not allow non ascii titles.
+    fn publish(&self, title: &str, message: &str, click: Option<&str>, tags: &str) -> Result<()> {
+        let mut request = self.agent
+            .post(&self.topic_url)
+            .query("title", title)
+            .query("tags", tags);
+
+        if let Some(click) = click {
+            request = request.query("click", click)
+        }
+
+        if let Some(ref token) = self.token {
+            request = request.set("Authorization", &format!("Bearer {token}"))
+        }
+
+        request.send_string(message).map_err(Box::new)?;
+        Ok(())
+    }
+}
+
+impl Notifier for NtfyNotifier {
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()> {
+        let link = video.link(links);
+        let message = format!("{}\n{}", video.formatted_duration(), video.links(links).join("\n"));
+        self.publish(&video_title(video), &message, Some(&link), "tv")
+    }
+
+    fn notify_error(&self, message: &str) -> Result<()> {
+        self.publish("new_tube error", message, None, "warning")
+    }
+}
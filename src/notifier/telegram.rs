@@ -0,0 +1,74 @@
+use frankenstein::{Api, SendMessageParams, TelegramApi};
+
+use crate::link::LinkStyle;
+use crate::notifier::{video_message, Notifier, Result};
+use crate::playlist_item::PlaylistItem;
+
+/// The maximum length of a telegram message
+const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// Sends the notifications to a telegram chat
+pub struct TelegramNotifier {
+    api: Api,
+    chat_id: i64,
+}
+
+impl TelegramNotifier {
+    pub fn new(api: Api, chat_id: i64) -> Self {
+        TelegramNotifier { api, chat_id }
+    }
+
+    /// Create a notifier for the given bot. If an api url is given, it is used instead of the official telegram server.
+    pub fn with_api_url(api_url: Option<&str>, api_key: &str, chat_id: i64) -> Self {
+        let api = match api_url {
+            Some(url) => Api::new_url(format!("{}/bot{api_key}", url.trim_end_matches('/'))),
+            None => Api::new(api_key)
+        };
+
+        Self::new(api, chat_id)
+    }
+
+    /// Send a text which might be longer than telegram allows. The text is split into
+    /// multiple messages at line breaks.
+    pub fn send_text(&self, text: &str) -> Result<()> {
+        let mut message = String::new();
+
+        for line in text.lines() {
+            if !message.is_empty() && message.len() + line.len() + 1 > MAX_MESSAGE_LENGTH {
+                self.send_message(&message)?;
+                message.clear();
+            }
+
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            message.push_str(line);
+        }
+
+        if !message.is_empty() {
+            self.send_message(&message)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_message(&self, message: &str) -> Result<()> {
+        let params = SendMessageParams::builder()
+            .chat_id(self.chat_id)
+            .text(message)
+            .build();
+
+        self.api.send_message(&params)?;
+        Ok(())
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()> {
+        self.send_text(&video_message(video, links))
+    }
+
+    fn notify_error(&self, message: &str) -> Result<()> {
+        self.send_text(message)
+    }
+}
@@ -0,0 +1,46 @@
+use serde_json::json;
+
+use crate::link::LinkStyle;
+use crate::notifier::{http_agent, Notifier, Result};
+use crate::playlist_item::PlaylistItem;
+
+/// Posts the notifications as JSON to an url.
+///
+/// A video is sent as {"type": "video", "video": {...}, "link": "..."},
+/// an error as {"type": "error", "message": "..."}.
+pub struct WebhookNotifier {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Self {
+        WebhookNotifier {
+            agent: http_agent(),
+            url: url.to_string(),
+        }
+    }
+
+    fn post(&self, body: serde_json::Value) -> Result<()> {
+        self.agent.post(&self.url).send_json(body).map_err(Box::new)?;
+        Ok(())
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify_video(&self, video: &PlaylistItem, links: &[LinkStyle]) -> Result<()> {
+        self.post(json!({
+            "type": "video",
+            "video": video,
+            "link": video.link(links),
+            "links": video.links(links),
+        }))
+    }
+
+    fn notify_error(&self, message: &str) -> Result<()> {
+        self.post(json!({
+            "type": "error",
+            "message": message,
+        }))
+    }
+}
@@ -0,0 +1,63 @@
+use error_generator::error;
+
+use crate::config::{Config, DownloadConfig, FeedConfig};
+use crate::feed::write_feeds;
+use crate::new_tube_service::database::DBError;
+use crate::new_tube_service::downloader::Downloader;
+use crate::new_tube_service::{FetchResult, NewTubeService};
+use crate::notifier::{create_notifiers, notify_all, Notifier, NotifierError};
+
+/// What gets the results of a fetch cycle, besides the telegram chats of the subscribers
+pub struct Outputs {
+    /// The notifiers from the config
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// Downloads the new videos, if configured
+    downloader: Option<Downloader>,
+    /// Where the feeds are written to, if configured
+    feeds: Option<FeedConfig>,
+    download_config: Option<DownloadConfig>,
+}
+
+impl Outputs {
+    pub fn new(config: &Config) -> Result<Self, OutputsError> {
+        Ok(Outputs {
+            notifiers: create_notifiers(&config.notifiers)?,
+            downloader: config.download.as_ref().map(Downloader::new).transpose()?,
+            feeds: config.feeds.clone(),
+            download_config: config.download.clone(),
+        })
+    }
+
+    /// Send the result to the notifiers, queue the new videos for download and write the feeds.
+    /// Videos which are still downloading are added to the feeds after the next fetch.
+    pub fn deliver(&self, service: &NewTubeService, result: &FetchResult) -> Result<(), DBError> {
+        notify_all(&self.notifiers, service.get_link_styles(None), result);
+
+        if let Some(ref downloader) = self.downloader {
+            downloader.enqueue_new_videos(result)?;
+        }
+
+        if let Some(ref feed_config) = self.feeds {
+            if let Err(err) = write_feeds(feed_config, self.download_config.as_ref(), service) {
+                println!("failed to write the feeds due to error: {}", err)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the running downloads. The queued ones are left for the next start if new_tube is stopping.
+    pub fn finish(&self) {
+        if let Some(ref downloader) = self.downloader {
+            downloader.finish()
+        }
+    }
+}
+
+#[error]
+pub enum OutputsError {
+    #[error(message = "Failed to create notifier: {_0}", impl_from)]
+    Notifier(NotifierError),
+    #[error(message = "Failed to start the downloader: {_0}", impl_from)]
+    Downloader(DBError),
+}
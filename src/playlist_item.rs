@@ -1,7 +1,15 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::ToSql;
+use serde::Serialize;
+
+use crate::link::LinkStyle;
 use crate::new_tube_service::yt_dlp::YTDLPItem;
 
 /// Represents the latest item from a YouTube playlist
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct PlaylistItem {
     /// ID of the playlist
     pub playlist_id: String,
@@ -16,24 +24,74 @@ pub struct PlaylistItem {
     pub uploader: String,
     /// The previous video id which was uploaded before the latest one.
     /// Required to check if a video was removed from a playlist.
-    pub previous_video_id: String
+    pub previous_video_id: String,
+    /// If the video is a regular upload, a livestream or a premiere
+    pub kind: VideoKind,
+    /// When an upcoming livestream or premiere starts, as a unix timestamp
+    pub release_timestamp: Option<i64>,
+    /// If the video is a YouTube short
+    pub short: bool,
 }
 
 impl PlaylistItem {
     pub fn new(yt_dlp_item: YTDLPItem, previous_video_id: String) -> Self {
         PlaylistItem {
+            kind: VideoKind::from_yt_dlp_item(&yt_dlp_item),
+            short: Self::is_short(&yt_dlp_item),
             playlist_id: yt_dlp_item.playlist_id,
             video_id: yt_dlp_item.id,
             title: yt_dlp_item.title,
             duration: yt_dlp_item.duration.unwrap_or_default(),
             uploader: yt_dlp_item.channel,
             previous_video_id: previous_video_id,
+            release_timestamp: yt_dlp_item.release_timestamp,
+        }
+    }
+
+    /// The state of a playlist without any videos. Every video found in it later is new
+    pub fn empty(playlist_id: &str) -> Self {
+        PlaylistItem {
+            playlist_id: playlist_id.to_string(),
+            video_id: String::new(),
+            title: String::new(),
+            duration: 0.0,
+            uploader: String::new(),
+            previous_video_id: String::new(),
+            kind: VideoKind::Upload,
+            release_timestamp: None,
+            short: false,
         }
     }
 
-    /// Create a full url to the video this playlist item represents
-    pub fn link(&self) -> String {
-        format!("https://www.youtube.com/watch?v={}", self.video_id)
+    /// Check if this is the state of a playlist without any videos
+    pub fn is_empty(&self) -> bool {
+        self.video_id.is_empty()
+    }
+
+    /// Guess if the item is a YouTube short, as yt-dlp does not tell it directly.
+    ///
+    /// An item is a short if its URL points to the shorts player or if it is a portrait video.
+    /// Shorts can be up to three minutes long and regular videos can be shorter than that, so the duration
+    /// tells nothing. Without the URL or the size, the item is no short.
+    fn is_short(item: &YTDLPItem) -> bool {
+        let shorts_url = item.url.as_deref().is_some_and(|url| url.contains("/shorts/"));
+        let portrait = matches!((item.width, item.height), (Some(width), Some(height)) if height > width);
+
+        shorts_url || portrait
+    }
+
+    /// Create a full url to the video this playlist item represents, in the first of the given styles.
+    /// Without any style, the link points to YouTube
+    pub fn link(&self, styles: &[LinkStyle]) -> String {
+        styles.first().unwrap_or(&LinkStyle::YouTube).link(&self.video_id)
+    }
+
+    /// Create a full url to the video in each of the given styles, at least one
+    pub fn links(&self, styles: &[LinkStyle]) -> Vec<String> {
+        match styles {
+            [] => vec![self.link(styles)],
+            _ => styles.iter().map(|style| style.link(&self.video_id)).collect()
+        }
     }
 
     /// Return the duration of the video in a properly formatted string
@@ -44,4 +102,159 @@ impl PlaylistItem {
         let hours = (secs / 60) / 60;
         format!("{hours}:{minutes}:{seconds}")
     }
-}
\ No newline at end of file
+
+    /// Return the release time of an upcoming video as 'YYYY-MM-DD HH:MM UTC'
+    pub fn formatted_release_time(&self) -> Option<String> {
+        let timestamp = self.release_timestamp?;
+        let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+        let seconds_of_day = timestamp.rem_euclid(86400);
+
+        Some(format!(
+            "{year}-{month:02}-{day:02} {:02}:{:02} UTC",
+            seconds_of_day / 3600,
+            (seconds_of_day / 60) % 60
+        ))
+    }
+
+    /// A short description of what happened with the video, which is shown before it in
+    /// notifications. Regular uploads need no headline.
+    pub fn headline(&self) -> Option<String> {
+        let release_time = self.formatted_release_time().unwrap_or_else(|| "an unknown time".to_string());
+
+        match self.kind {
+            VideoKind::Upload => None,
+            VideoKind::Live => Some("Now live".to_string()),
+            VideoKind::Upcoming => Some(format!("Livestream scheduled for {release_time}")),
+            VideoKind::Premiere => Some(format!("Premiere scheduled for {release_time}")),
+            VideoKind::PastLive => Some("VOD available".to_string()),
+        }
+    }
+}
+
+/// Convert the days since 1970-01-01 to a (year, month, day) date (http://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// What kind of video a playlist item is
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoKind {
+    /// A regular video
+    Upload,
+    /// A livestream which is currently running
+    Live,
+    /// A livestream which starts in the future
+    Upcoming,
+    /// A premiere of a pre-recorded video which starts in the future
+    Premiere,
+    /// The recording of a finished livestream
+    PastLive,
+}
+
+impl VideoKind {
+    pub const ALL: [VideoKind; 5] = [VideoKind::Upload, VideoKind::Live, VideoKind::Upcoming, VideoKind::Premiere, VideoKind::PastLive];
+
+    /// Derive the kind from the live_status yt-dlp returned.
+    ///
+    /// yt-dlp does not distinguish between upcoming livestreams and premieres, but only premieres
+    /// already know their duration.
+    fn from_yt_dlp_item(item: &YTDLPItem) -> Self {
+        match item.live_status.as_deref() {
+            Some("is_live") => VideoKind::Live,
+            Some("is_upcoming") if item.duration.is_some() => VideoKind::Premiere,
+            Some("is_upcoming") => VideoKind::Upcoming,
+            Some("was_live" | "post_live") => VideoKind::PastLive,
+            _ => VideoKind::Upload
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoKind::Upload => "upload",
+            VideoKind::Live => "live",
+            VideoKind::Upcoming => "upcoming",
+            VideoKind::Premiere => "premiere",
+            VideoKind::PastLive => "past_live",
+        }
+    }
+}
+
+impl Display for VideoKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for VideoKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VideoKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("unknown video kind '{s}', expected one of upload, live, upcoming, premiere, past_live"))
+    }
+}
+
+impl ToSql for VideoKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for VideoKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(duration: f32, width: Option<u32>, height: Option<u32>) -> YTDLPItem {
+        YTDLPItem {
+            playlist_id: "UUaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            id: "a5xvRqg9yDc".to_string(),
+            title: "Video".to_string(),
+            duration: Some(duration),
+            channel: "Wood Works".to_string(),
+            live_status: None,
+            release_timestamp: None,
+            url: None,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn shorts_are_recognized_by_their_size() {
+        assert!(PlaylistItem::is_short(&item(170.0, Some(1080), Some(1920))));
+        assert!(!PlaylistItem::is_short(&item(45.0, Some(1920), Some(1080))));
+    }
+
+    #[test]
+    fn shorts_are_recognized_by_their_url() {
+        let short = YTDLPItem {
+            url: Some("https://www.youtube.com/shorts/a5xvRqg9yDc".to_string()),
+            ..item(150.0, None, None)
+        };
+        assert!(PlaylistItem::is_short(&short));
+    }
+
+    #[test]
+    fn the_duration_does_not_decide() {
+        assert!(!PlaylistItem::is_short(&item(45.0, None, None)));
+        assert!(!PlaylistItem::is_short(&item(150.0, None, None)));
+    }
+}
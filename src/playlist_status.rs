@@ -0,0 +1,18 @@
+use serde::Serialize;
+
+/// How well fetching a playlist worked recently
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistStatus {
+    /// ID of the playlist
+    pub playlist_id: String,
+    /// The channel name which uploaded the latest video
+    pub uploader: String,
+    /// When the playlist was last fetched successfully, formatted as 'YYYY-MM-DD HH:MM:SS' (UTC)
+    pub last_success: Option<String>,
+    /// The error of the last failed fetch. Cleared after a successful one
+    pub last_error: Option<String>,
+    /// How many fetches in a row failed
+    pub consecutive_failures: u32,
+    /// Disabled playlists are no longer fetched
+    pub enabled: bool,
+}
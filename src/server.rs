@@ -0,0 +1,487 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use error_generator::error;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::config::Config;
+use crate::feed::{escape, mime_type};
+use crate::link::LinkStyle;
+use crate::new_tube_service::yt_dlp;
+use crate::new_tube_service::{NewTubeService, NewTubeServiceError};
+use crate::outputs::{Outputs, OutputsError};
+use crate::playlist_item::PlaylistItem;
+use crate::playlist_status::PlaylistStatus;
+
+/// The maximum size of a request body
+const MAX_BODY_LENGTH: u64 = 64 * 1024;
+
+/// How many videos of the history the dashboard shows
+const DASHBOARD_HISTORY_LENGTH: usize = 50;
+
+/// The state shared by all requests and the scheduled fetches
+struct State {
+    service: NewTubeService,
+    outputs: Outputs,
+    /// When the next scheduled fetch starts, as a unix timestamp
+    next_run: AtomicU64,
+    fetch_interval: Duration,
+    token: String,
+    /// The directory the feeds are written to, served at /feeds
+    feeds_dir: Option<PathBuf>,
+    /// The download library, served at /media
+    media_dir: Option<PathBuf>,
+}
+
+/// The body of the requests which add or replace a playlist
+#[derive(Deserialize)]
+struct PlaylistRequest {
+    /// Anything 'add' accepts, like a playlist id or a channel URL
+    playlist: String,
+}
+
+type ApiResult = Result<(u16, Value), NewTubeServiceError>;
+
+/// A HTTP server with a dashboard and a JSON api. It also fetches the new videos periodically,
+/// like the bot does, but only delivers them to the outputs from the config.
+///
+/// The api:
+/// - GET /api/playlists: the status of every playlist
+/// - POST /api/playlists with {"playlist": "..."}: add a playlist
+/// - PUT /api/playlists/<id> with {"playlist": "..."}: replace a playlist
+/// - DELETE /api/playlists/<id>: delete a playlist
+/// - GET /api/last: the latest video of every playlist
+/// - GET /api/history?channel=...&since=YYYY-MM-DD: the video history
+/// - GET /api/schedule: when the next fetch starts
+/// - POST /api/fetch: fetch the new videos right now
+///
+/// Every request must contain the token from the config as 'Authorization: Bearer <token>' header or as
+/// 'token' query parameter. The server does not start without a token. This includes the feeds and the downloaded
+/// files at /feeds and /media, which podcast apps get with the token in the links (see FeedConfig.token).
+pub struct Server;
+
+impl Server {
+    pub fn run(config: Config) -> Result<(), ServerError> {
+        let Some(token) = config.server.token.clone().filter(|token| !token.is_empty()) else {
+            return Err(ServerError::MissingToken);
+        };
+
+        let state = Arc::new(State {
+            service: NewTubeService::new(&config)?,
+            outputs: Outputs::new(&config)?,
+            next_run: AtomicU64::new(0),
+            fetch_interval: Duration::from_secs(config.bot_fetch_schedule as u64 * 60),
+            token,
+            feeds_dir: config.feeds.as_ref().map(|feeds| feeds.output_dir.clone()),
+            media_dir: config.download.as_ref().map(|download| download.library_dir.clone()),
+        });
+
+        let server = tiny_http::Server::http(&config.server.address)?;
+        println!("Serving on http://{}", config.server.address);
+
+        let scheduler_state = state.clone();
+        thread::spawn(move || Self::fetch_periodically(&scheduler_state));
+
+        for request in server.incoming_requests() {
+            let state = state.clone();
+            thread::spawn(move || Self::handle_request(&state, request));
+        }
+
+        Ok(())
+    }
+
+    fn fetch_periodically(state: &State) {
+        loop {
+            state.next_run.store(now() + state.fetch_interval.as_secs(), Ordering::Relaxed);
+            thread::sleep(state.fetch_interval);
+
+            if let Err(err) = Self::fetch(state) {
+                println!("An error occurred while fetching new videos: {}", err)
+            }
+        }
+    }
+
+    /// Fetch the new videos and pass them to the outputs
+    fn fetch(state: &State) -> ApiResult {
+        let result = state.service.get_new_videos_and_update_database()?;
+        state.outputs.deliver(&state.service, &result)?;
+
+        let failures = result.failures
+            .iter()
+            .map(|failure| json!({ "playlist_id": failure.playlist_id, "error": failure.error.to_string() }))
+            .collect::<Vec<_>>();
+
+        Ok((200, json!({
+            "new_videos": result.new_videos,
+            "failures": failures,
+            "disabled": result.disabled,
+            "skipped": result.skipped,
+        })))
+    }
+
+    fn handle_request(state: &State, mut request: Request) {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| decode(segment, false))
+            .collect::<Vec<_>>();
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+        let method = request.method().clone();
+
+        let response = match (method, segments.as_slice()) {
+            _ if !Self::is_authorized(state, &request, query) => Self::json_response(401, json!({ "error": "missing or wrong token" })),
+            (Method::Get, ["feeds", file @ ..]) => Self::serve_file(state.feeds_dir.as_deref(), file),
+            (Method::Get, ["media", file @ ..]) => Self::serve_file(state.media_dir.as_deref(), file),
+            (Method::Get, []) => match Self::dashboard(state) {
+                Ok(html) => Response::from_string(html).with_header(content_type("text/html; charset=utf-8")).boxed(),
+                Err(err) => Self::json_response(500, json!({ "error": err.to_string() }))
+            },
+            (method, ["api", route @ ..]) => match Self::handle_api_request(state, &mut request, method, route, query) {
+                Ok((status, body)) => Self::json_response(status, body),
+                Err(err) => Self::json_response(error_status(&err), json!({ "error": err.to_string() }))
+            },
+            _ => Self::json_response(404, json!({ "error": "not found" }))
+        };
+
+        if let Err(err) = request.respond(response) {
+            println!("failed to respond to a request due to error: {err}")
+        }
+    }
+
+    fn handle_api_request(state: &State, request: &mut Request, method: Method, route: &[&str], query: &str) -> ApiResult {
+        match (method, route) {
+            (Method::Get, ["playlists"]) => Ok((200, json!(Self::service(state).get_statuses()?))),
+            (Method::Post, ["playlists"]) => match Self::read_body::<PlaylistRequest>(request) {
+                Ok(body) => Self::service(state)
+                    .add_playlist(&body.playlist)
+                    .map(|id| (201, json!({ "playlist_id": id }))),
+                Err(err) => Ok((400, json!({ "error": err })))
+            },
+            (Method::Put, ["playlists", id]) => match Self::read_body::<PlaylistRequest>(request) {
+                Ok(body) => Self::service(state)
+                    .replace(id, &body.playlist)
+                    .map(|_| (200, json!({}))),
+                Err(err) => Ok((400, json!({ "error": err })))
+            },
+            (Method::Delete, ["playlists", id]) => Self::service(state).delete(id).map(|_| (200, json!({}))),
+            (Method::Get, ["last"]) => Ok((200, json!(Self::service(state).get_last_items()?))),
+            (Method::Get, ["history"]) => {
+                let channel = query_parameter(query, "channel");
+                let since = query_parameter(query, "since");
+                Ok((200, json!(Self::service(state).get_history(channel.as_deref(), since.as_deref())?)))
+            }
+            (Method::Get, ["schedule"]) => Ok((200, json!({
+                "interval_minutes": state.fetch_interval.as_secs() / 60,
+                "next_run": state.next_run.load(Ordering::Relaxed),
+            }))),
+            (Method::Post, ["fetch"]) => Self::fetch(state),
+            _ => Ok((404, json!({ "error": "not found" })))
+        }
+    }
+
+    fn service(state: &State) -> &NewTubeService {
+        &state.service
+    }
+
+    fn is_authorized(state: &State, request: &Request, query: &str) -> bool {
+        let token = &state.token;
+        let header_matches = request
+            .headers()
+            .iter()
+            .filter(|header| header.field.equiv("Authorization"))
+            .filter_map(|header| header.value.as_str().strip_prefix("Bearer "))
+            .any(|value| is_same_token(value, token));
+
+        header_matches || query_parameter(query, "token").is_some_and(|value| is_same_token(&value, token))
+    }
+
+    fn read_body<T: for<'de> Deserialize<'de>>(request: &mut Request) -> Result<T, String> {
+        let mut body = String::new();
+        request
+            .as_reader()
+            .take(MAX_BODY_LENGTH)
+            .read_to_string(&mut body)
+            .map_err(|err| err.to_string())?;
+        serde_json::from_str(&body).map_err(|err| err.to_string())
+    }
+
+    /// Serve a file from the directory. Hidden files and paths leaving the directory are not served.
+    fn serve_file(directory: Option<&Path>, file: &[&str]) -> Response<Box<dyn Read + Send>> {
+        let not_found = || Self::json_response(404, json!({ "error": "not found" }));
+
+        let Some(path) = directory.and_then(|directory| file_path(directory, file)) else {
+            return not_found();
+        };
+
+        match File::open(&path) {
+            Ok(file) if path.is_file() => {
+                let content_type = match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("xml") => "application/rss+xml; charset=utf-8",
+                    Some("atom") => "application/atom+xml; charset=utf-8",
+                    _ => mime_type(&path)
+                };
+                Response::from_file(file).with_header(self::content_type(content_type)).boxed()
+            }
+            _ => not_found()
+        }
+    }
+
+    fn json_response(status: u16, body: Value) -> Response<Box<dyn Read + Send>> {
+        Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type("application/json"))
+            .boxed()
+    }
+
+    /// Create the html of the dashboard
+    fn dashboard(state: &State) -> Result<String, NewTubeServiceError> {
+        let (statuses, last_items, history, links) = {
+            let service = Self::service(state);
+            let mut history = service.get_history(None, None)?;
+            history.truncate(DASHBOARD_HISTORY_LENGTH);
+            (service.get_statuses()?, service.get_last_items()?, history, service.get_link_styles(None).to_vec())
+        };
+
+        let next_run = state.next_run.load(Ordering::Relaxed);
+        let next_run = match next_run {
+            0 => "not scheduled yet".to_string(),
+            _ => format!("in {} minutes", next_run.saturating_sub(now()).div_ceil(60))
+        };
+
+        let failures = statuses
+            .iter()
+            .filter(|status| status.last_error.is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Ok(format!(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>new_tube</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>new_tube</h1>
+<p>Next fetch: {}</p>
+<h2>Failures</h2>
+{}
+<h2>Latest videos</h2>
+{}
+<h2>Subscriptions</h2>
+{}
+<h2>History</h2>
+{}
+</body>
+</html>
+",
+            escape(&next_run),
+            Self::status_table(&failures),
+            Self::video_table(&last_items, &links),
+            Self::status_table(&statuses),
+            Self::video_table(&history, &links),
+        ))
+    }
+
+    fn status_table(statuses: &[PlaylistStatus]) -> String {
+        html_table(
+            &["Channel", "Playlist ID", "Enabled", "Failures", "Last success", "Last error"],
+            statuses.iter().map(|status| vec![
+                escape(&status.uploader),
+                escape(&status.playlist_id),
+                if status.enabled { "yes".to_string() } else { "no".to_string() },
+                status.consecutive_failures.to_string(),
+                escape(status.last_success.as_deref().unwrap_or("never")),
+                escape(status.last_error.as_deref().unwrap_or_default()),
+            ]),
+        )
+    }
+
+    fn video_table(videos: &[PlaylistItem], links: &[LinkStyle]) -> String {
+        html_table(
+            &["Channel", "Video", "Duration", "Status"],
+            videos.iter().map(|video| vec![
+                escape(&video.uploader),
+                format!("<a href=\"{}\">{}</a>", escape(&video.link(links)), escape(&video.title)),
+                video.formatted_duration(),
+                escape(&video.headline().unwrap_or_default()),
+            ]),
+        )
+    }
+}
+
+/// Create a html table. The cells must already be escaped
+fn html_table(header: &[&str], rows: impl Iterator<Item=Vec<String>>) -> String {
+    let header = header.iter().map(|cell| format!("<th>{cell}</th>")).collect::<String>();
+    let rows = rows
+        .map(|row| format!("<tr>{}</tr>", row.iter().map(|cell| format!("<td>{cell}</td>")).collect::<String>()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if rows.is_empty() {
+        return "<p>Nothing here</p>".to_string();
+    }
+
+    format!("<table>\n<tr>{header}</tr>\n{rows}\n</table>")
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("the content type should be a valid header")
+}
+
+/// Map the error to a http status code
+fn error_status(error: &NewTubeServiceError) -> u16 {
+    match (error, error.yt_dlp_error()) {
+        (_, Some(yt_dlp::Error::PlaylistUnavailable(_))) => 404,
+        (_, Some(yt_dlp::Error::RateLimited(_))) => 503,
+        (_, Some(yt_dlp::Error::TimedOut(_))) => 504,
+        (_, Some(_)) => 502,
+        (NewTubeServiceError::UnknownPlaylist(_), _) => 404,
+        (NewTubeServiceError::ResolveError(_), _) => 400,
+        (NewTubeServiceError::InvalidDate(_), _) => 400,
+        _ => 500
+    }
+}
+
+/// Get the path of the file in the directory, given by the decoded segments of the url.
+/// Returns None for hidden files and for paths which would leave the directory, like absolute paths or '..'.
+fn file_path(directory: &Path, file: &[&str]) -> Option<PathBuf> {
+    if file.is_empty() {
+        return None;
+    }
+
+    let mut path = directory.to_path_buf();
+
+    for segment in file {
+        // a decoded segment might contain a separator, like %2F
+        if segment.starts_with('.') || segment.contains(['/', '\\']) {
+            return None;
+        }
+
+        let mut components = Path::new(segment).components();
+
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => path.push(segment),
+            _ => return None
+        }
+    }
+
+    // symlinks might still point outside the directory
+    let path = path.canonicalize().ok()?;
+    path.starts_with(directory.canonicalize().ok()?).then_some(path)
+}
+
+/// Compare the tokens in constant time, so the time of a response does not tell how much of a token is right
+fn is_same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn query_parameter(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode(value, true))
+}
+
+/// Decode a percent encoded url component. In query parameters, '+' is a space.
+fn decode(component: &str, is_query: bool) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+
+        match (bytes[index], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (b'+', _) if is_query => decoded.push(b' '),
+            (byte, _) => decoded.push(byte)
+        }
+
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the system time should be after the unix epoch")
+        .as_secs()
+}
+
+#[error]
+pub enum ServerError {
+    #[error(message = "{_0}", impl_from)]
+    NewTubeService(NewTubeServiceError),
+    #[error(message = "{_0}", impl_from)]
+    Outputs(OutputsError),
+    #[error(message = "A token is required to start the server, set it in the server section of the config")]
+    MissingToken,
+    #[error(message = "Failed to start the server: {_0}", impl_from)]
+    StartFailed(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn file_path_rejects_paths_outside_the_directory() {
+        let root = env::temp_dir().join(format!("new_tube_server_test_{}", std::process::id()));
+        let directory = root.join("media");
+        fs::create_dir_all(directory.join("channel")).unwrap();
+        fs::write(directory.join("channel").join("video.mp4"), "video").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let decoded = |url: &str| url.split('/').filter(|segment| !segment.is_empty()).map(|segment| decode(segment, false)).collect::<Vec<_>>();
+        let resolve = |url: &str| file_path(&directory, &decoded(url).iter().map(String::as_str).collect::<Vec<_>>());
+
+        assert_eq!(resolve("channel/video.mp4"), Some(directory.join("channel").join("video.mp4").canonicalize().unwrap()));
+        assert_eq!(resolve("%2Fetc%2Fpasswd"), None);
+        assert_eq!(resolve("channel%2F..%2F..%2Fsecret.txt"), None);
+        assert_eq!(resolve("../secret.txt"), None);
+        assert_eq!(resolve("..%5Csecret.txt"), None);
+        assert_eq!(resolve(".hidden"), None);
+        assert_eq!(resolve(""), None);
+        assert_eq!(resolve("channel/missing.mp4"), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn tokens_must_match_completely() {
+        assert!(is_same_token("secret", "secret"));
+        assert!(!is_same_token("secreT", "secret"));
+        assert!(!is_same_token("secret", "secret2"));
+        assert!(!is_same_token("", "secret"));
+    }
+
+    #[test]
+    fn invalid_history_dates_are_bad_requests() {
+        assert_eq!(error_status(&NewTubeServiceError::InvalidDate("garbage".to_string())), 400);
+    }
+}
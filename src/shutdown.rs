@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, AtomicUsize};
+
+/// If new_tube was told to stop
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// If the running command stops on its own once it was told to, instead of being exited right away
+static GRACEFUL: AtomicBool = AtomicBool::new(false);
+/// The signal which told new_tube to stop
+#[cfg(unix)]
+static SIGNAL: AtomicI32 = AtomicI32::new(libc::SIGINT);
+/// The signal handler ctrlc installed, which the signals are passed on to once they are recorded
+#[cfg(unix)]
+static CTRLC_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Let the running command stop on its own when new_tube is told to stop, so it can finish what it is doing.
+/// The command must check [requested] regularly.
+pub fn stop_gracefully() {
+    GRACEFUL.store(true, Ordering::SeqCst)
+}
+
+/// Tell the running command to stop. Returns if it stops on its own, otherwise new_tube must be exited.
+pub fn request() -> bool {
+    REQUESTED.store(true, Ordering::SeqCst);
+    GRACEFUL.load(Ordering::SeqCst)
+}
+
+/// If new_tube was told to stop
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// The exit code of new_tube once it was told to stop. Like a shell reports it, this is 128 plus the number of the signal.
+#[cfg(unix)]
+pub fn exit_code() -> i32 {
+    128 + SIGNAL.load(Ordering::SeqCst)
+}
+
+/// The exit code of new_tube once it was told to stop, which is the one of Ctrl-C
+#[cfg(not(unix))]
+pub fn exit_code() -> i32 {
+    130
+}
+
+/// ctrlc does not tell which signal it got, so the signals it handles are recorded before they are passed on to it.
+/// Must be called after the ctrlc handler was set.
+#[cfg(unix)]
+pub fn record_signals() {
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = record_signal as extern "C" fn(libc::c_int) as usize;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signal, &action, &mut previous) != 0 {
+                continue;
+            }
+
+            // only a plain handler function can be called with the signal alone
+            let is_function = previous.sa_sigaction != libc::SIG_DFL && previous.sa_sigaction != libc::SIG_IGN;
+            if is_function && previous.sa_flags & libc::SA_SIGINFO == 0 {
+                CTRLC_HANDLER.store(previous.sa_sigaction, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn record_signal(signal: libc::c_int) {
+    SIGNAL.store(signal, Ordering::SeqCst);
+
+    let handler = CTRLC_HANDLER.load(Ordering::SeqCst);
+    if handler != 0 {
+        let handler: extern "C" fn(libc::c_int) = unsafe { std::mem::transmute(handler) };
+        handler(signal)
+    }
+}
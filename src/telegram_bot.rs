@@ -1,53 +1,177 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 use clokwerk::{Scheduler, TimeUnits};
 use error_generator::error;
-use frankenstein::{AllowedUpdate, Api, GetUpdatesParams, Message, SendMessageParams, TelegramApi, UpdateContent};
+use frankenstein::{AllowedUpdate, Api, BotCommand, GetUpdatesParams, Message, SendMessageParams, SetMyCommandsParams, TelegramApi, UpdateContent};
 
 use crate::config::Config;
 use crate::environment::{get_allowed_bot_user, get_default_telegram_channel_id, get_telegram_api_key};
+use crate::filter::Filter;
+use crate::link::{format_link_styles, parse_link_styles, LinkStyle};
 use crate::new_tube_service::{NewTubeService, NewTubeServiceError};
-use crate::playlist_item::PlaylistItem;
+use crate::notifier::telegram::TelegramNotifier;
+use crate::notifier::{notify_errors, video_message, Notifier};
+use crate::outputs::{Outputs, OutputsError};
+use crate::playlist_item::{PlaylistItem, VideoKind};
+use crate::shutdown;
+use crate::user::User;
+
+/// All commands the bot understands, with their description shown in telegram.
+const COMMANDS: &[(&str, &str)] = &[
+    ("start", "Request access to the bot"),
+    ("help", "Show all commands"),
+    ("health", "Check if the bot is still running"),
+    ("add", "<playlist id, channel or video URL> Subscribe to a playlist"),
+    ("delete", "<playlist id> Unsubscribe from a playlist (the admin deletes it for everyone)"),
+    ("replace", "<old playlist id> <new playlist id> Replace a subscription with another one"),
+    ("list", "Show the ids of your subscribed playlists"),
+    ("last", "Show the last video of your subscribed playlists"),
+    ("new", "Fetch the new videos right now"),
+    ("notify", "<playlist id> <upload|live|upcoming|premiere|past_live> <on|off> Set which kinds of videos you get notified about"),
+    ("filter_add", "<playlist id> <title-include|title-exclude|min-duration|max-duration|uploader> <value> Add a filter rule for your notifications"),
+    ("filter_list", "[playlist id] Show your filter rules"),
+    ("filter_remove", "<filter id> Remove one of your filter rules"),
+    ("links", "[youtube|nocookie|invidious=<url>|piped=<url>|default ...] Show or choose where the links to videos point to"),
+    ("users", "Show all users (admin only)"),
+    ("approve", "<username> Allow a user to use the bot (admin only)"),
+    ("revoke", "<username> Remove a user and its subscriptions (admin only)"),
+];
+
+/// The service is shared between the scheduler thread, which reads the updates, and the fetch thread
+type SharedService = Arc<NewTubeService>;
 
 pub struct Bot;
 
 impl Bot {
+    /// Run the bot until new_tube is told to stop. The running fetch, downloads and hooks are finished before it returns.
     pub fn run(config: Config) -> Result<(), BotError> {
         let mut scheduler = Scheduler::new();
         let api = Api::new(&get_telegram_api_key());
         let chat_id = get_default_telegram_channel_id();
-        let new_tube_service = NewTubeService::new()?;
+        let new_tube_service = Arc::new(NewTubeService::new(&config)?);
+        let outputs = Arc::new(Outputs::new(&config)?);
+        let fetch_interval = Duration::from_secs(config.bot_fetch_schedule as u64 * 60);
+        let (fetch_requests, fetch_requests_receiver) = channel();
+        shutdown::stop_gracefully();
+
+        Self::register_commands(&api);
 
-        scheduler.every(10.seconds()).run(Self::read_updates(api.clone(), chat_id));
-        scheduler.every(config.bot_fetch_schedule.minutes()).run(Self::fetch_videos(api.clone(), chat_id, new_tube_service));
+        scheduler.every(10.seconds()).run(Self::read_updates(api.clone(), new_tube_service.clone(), fetch_requests));
+        let (fetch_api, fetch_service, fetch_outputs) = (api.clone(), new_tube_service.clone(), outputs.clone());
+        let fetcher = thread::spawn(move || Self::fetch_periodically(&fetch_api, chat_id, &fetch_service, &fetch_outputs, fetch_interval, fetch_requests_receiver));
         Self::send_message(&api, chat_id, "Started");
         println!("Bot started");
 
-        loop {
+        // the scheduler owns the sender of the fetch requests, so the fetch thread stops once it is gone
+        Self::run_until_shutdown(scheduler);
+        let _ = fetcher.join();
+        outputs.finish();
+        new_tube_service.finish_hooks();
+        println!("Bot stopped");
+
+        Ok(())
+    }
+
+    /// Run the scheduled jobs until new_tube is told to stop. The scheduler and its jobs are dropped afterwards.
+    fn run_until_shutdown(mut scheduler: Scheduler) {
+        while !shutdown::requested() {
             scheduler.run_pending();
             thread::sleep(Duration::from_millis(500));
         }
     }
 
-    fn fetch_videos(api: Api, chat_id: i64, new_tube_service: NewTubeService) -> impl FnMut() {
-        move || {
-            match new_tube_service.get_new_videos_and_update_database() {
-                Ok(new_videos) => new_videos.into_iter()
-                    .map(Self::item_to_telegram_message)
-                    .for_each(|m| Self::send_message(&api, chat_id, m)),
-                Err(err) => println!("An error occurred while fetching new videos: {}", err)
+    /// Tell telegram which commands the bot understands, so they can be suggested to the user
+    fn register_commands(api: &Api) {
+        let commands = COMMANDS
+            .iter()
+            .map(|(command, description)| BotCommand::builder()
+                .command(*command)
+                .description(*description)
+                .build())
+            .collect();
+        let params = SetMyCommandsParams::builder()
+            .commands(commands)
+            .build();
+
+        if let Err(err) = api.set_my_commands(&params) {
+            println!("failed to register the bot commands due to error: {}", err)
+        }
+    }
+
+    /// Fetch and deliver the new videos on a thread of their own, so the bot keeps answering commands meanwhile.
+    /// A fetch requested with /new starts right away, and the chat which requested it is told how many videos were found.
+    fn fetch_periodically(
+        api: &Api,
+        default_chat_id: i64,
+        service: &NewTubeService,
+        outputs: &Outputs,
+        interval: Duration,
+        requests: Receiver<i64>,
+    ) {
+        loop {
+            let requested_by = match requests.recv_timeout(interval) {
+                Ok(chat_id) => Some(chat_id),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return
+            };
+
+            match (Self::fetch_and_deliver_videos(api, default_chat_id, service, outputs), requested_by) {
+                (Ok(amount), Some(chat_id)) => Self::send_message(api, chat_id, format!("Found {amount} new videos")),
+                (Err(err), Some(chat_id)) => Self::send_message(api, chat_id, format!("Error: {err}")),
+                (Ok(_), None) => {}
+                (Err(err), None) => println!("An error occurred while fetching new videos: {}", err)
             }
         }
     }
 
+    /// Fetch the new videos and send each of them to the chats of its subscribers.
+    /// Every playlist is only fetched once, no matter how many users subscribed to it.
+    /// Playlists which could not be fetched are reported to the default chat in a single message,
+    /// playlists which got disabled are reported separately.
+    /// Afterwards, the result is passed to the other outputs, like the notifiers from the config.
+    /// Returns the amount of new videos.
+    fn fetch_and_deliver_videos(
+        api: &Api,
+        default_chat_id: i64,
+        service: &NewTubeService,
+        outputs: &Outputs,
+    ) -> Result<usize, NewTubeServiceError> {
+        let result = service.get_new_videos_and_update_database()?;
+        let amount = result.new_videos.len() + result.separate_chat_videos.len();
+
+        notify_errors(&TelegramNotifier::new(api.clone(), default_chat_id), &result);
+
+        for video in &result.new_videos {
+            for (chat_id, links) in service.get_chats_to_notify(video, default_chat_id)? {
+                Self::notify_chat(api, chat_id, video, &links)
+            }
+        }
+
+        for (chat_id, video) in &result.separate_chat_videos {
+            Self::notify_chat(api, *chat_id, video, service.get_link_styles(None))
+        }
+
+        outputs.deliver(service, &result)?;
+
+        Ok(amount)
+    }
+
+    fn notify_chat(api: &Api, chat_id: i64, video: &PlaylistItem, links: &[LinkStyle]) {
+        if let Err(err) = TelegramNotifier::new(api.clone(), chat_id).notify_video(video, links) {
+            println!("failed to send message due to error: {}", err)
+        }
+    }
+
     /// Get the latest updates to the bot and process them.
     ///
     /// The updates are returned by telegrams getUpdates method (https://core.telegram.org/bots/api#getupdates).
     /// A last update id and a message filter is provided. The last update id is important, as
     /// an update is only considered processed if an id larger than its own was provided as the 'offset'
     /// parameter. Therefore, the last update id is stored and provided as a parameter.
-    fn read_updates(api: Api, chat_id: i64) -> impl FnMut() {
+    fn read_updates(api: Api, new_tube_service: SharedService, fetch_requests: Sender<i64>) -> impl FnMut() {
         let mut last_update_id = 0;
 
         move || {
@@ -62,7 +186,7 @@ impl Bot {
                         last_update_id = update.update_id;
 
                         if let UpdateContent::Message(message) = update.content {
-                            Self::process_update_message(&api, chat_id, message)
+                            Self::process_update_message(&api, &new_tube_service, &fetch_requests, message)
                         }
                     }
                 }
@@ -73,32 +197,224 @@ impl Bot {
         }
     }
 
-    fn process_update_message(api: &Api, chat_id: i64, message: Message) {
-        if !Self::sender_is_valid(&message) {
+    /// Execute the command in the given message. The response is sent to the chat the message came from.
+    fn process_update_message(
+        api: &Api,
+        service: &NewTubeService,
+        fetch_requests: &Sender<i64>,
+        message: Message,
+    ) {
+        let chat_id = message.chat.id;
+
+        let user = match Self::identify_sender(api, service, &message) {
+            Ok(Some(user)) => user,
+            Ok(None) => return,
+            Err(err) => return Self::send_message(api, chat_id, format!("Error: {err}"))
+        };
+
+        if !user.approved {
+            return Self::send_message(api, chat_id, "You are not allowed to use this bot yet. An admin was asked for approval.");
+        }
+
+        let Some(ref text) = message.text else {
             return;
+        };
+
+        let mut words = text.split_whitespace();
+        // in group chats, commands might be suffixed with the bot name, like /add@new_tube_bot
+        let command = words.next().unwrap_or_default().split('@').next().unwrap_or_default();
+        let arguments = words.collect::<Vec<_>>();
+
+        let response = match (command, arguments.as_slice()) {
+            ("/start", []) => Ok("You can use the bot. Your videos are sent to this chat. Send /help to see all commands.".to_string()),
+            ("/help", []) => Ok(Self::usage()),
+            // simply check if the bot is still running
+            ("/health", []) => Ok("I am alive".to_string()),
+            ("/add", [input]) => service.subscribe(user.id, input).map(|id| format!("Subscribed to playlist {id}")),
+            // like the CLI, the admin deletes the playlist for everyone
+            ("/delete", [id]) if user.admin => service.delete(id).map(|_| format!("Deleted playlist {id}")),
+            ("/delete", [id]) => service.unsubscribe(user.id, id).map(|_| format!("Unsubscribed from playlist {id}")),
+            ("/replace", [old_id, new_id]) => service
+                .replace_subscription(user.id, old_id, new_id)
+                .map(|new_id| format!("Replaced playlist {old_id} with {new_id}")),
+            ("/list", []) => Self::visible_playlist_ids(service, &user).map(|ids| if ids.is_empty() {
+                "There are no playlists yet".to_string()
+            } else {
+                ids.join("\n")
+            }),
+            ("/last", []) => Self::visible_playlist_ids(service, &user)
+                .and_then(|ids| Ok(service
+                    .get_last_items()?
+                    .into_iter()
+                    .filter(|item| ids.contains(&item.playlist_id))
+                    .collect()))
+                .map(|items| Self::items_to_telegram_message(items, service.get_link_styles(Some(&user)))),
+            // the new videos are delivered to all subscribers, not just the sender. The fetch thread tells how many it found
+            ("/new", []) => Ok(match fetch_requests.send(chat_id) {
+                Ok(_) => "Fetching the new videos".to_string(),
+                Err(_) => "Cannot fetch the new videos, as the fetch thread stopped".to_string()
+            }),
+            ("/notify", [input, kind, notify]) => Self::set_kind_notification(service, &user, input, kind, notify),
+            // regexes and channel names might contain spaces
+            ("/filter_add", [input, kind, value @ ..]) if !value.is_empty() => service
+                .add_filter(Some(user.id), input, kind, &value.join(" "))
+                .map(|id| format!("Added filter {id}")),
+            ("/filter_list", []) => service.get_filters(Some(&user), None).map(Self::filters_to_telegram_message),
+            ("/filter_list", [input]) => service.get_filters(Some(&user), Some(input)).map(Self::filters_to_telegram_message),
+            ("/filter_remove", [id]) => match id.parse() {
+                Ok(id) => service.remove_filter(Some(&user), id).map(|_| format!("Removed filter {id}")),
+                Err(_) => Ok(format!("Invalid filter id {id}"))
+            },
+            ("/links", []) => Ok(format!("Your links: {}", format_link_styles(service.get_link_styles(Some(&user))))),
+            ("/links", ["default"]) => service
+                .save_user(&User { links: None, ..user.clone() })
+                .map(|_| "Your links use the default styles again".to_string()),
+            ("/links", styles) => Self::set_link_styles(service, &user, &styles.join(" ")),
+            ("/users", []) if user.admin => service.get_users().map(Self::users_to_telegram_message),
+            ("/approve", [username]) if user.admin => Self::approve(api, service, username),
+            ("/revoke", [username]) if user.admin => Self::revoke(service, username),
+            _ => Ok(format!("Unknown command. {}", Self::usage()))
+        };
+
+        match response {
+            Ok(text) => Self::send_long_message(api, chat_id, &text),
+            Err(err) => Self::send_message(api, chat_id, format!("Error: {err}"))
+        }
+    }
+
+    /// Find the user who sent the message. Unknown users are registered and must be approved by an admin,
+    /// except for the user defined in NEW_TUBE_ALLOWED_BOT_USER, who is always an approved admin.
+    /// The videos of a user are sent to the chat of their first message, or of their last /start.
+    ///
+    /// Returns None if the message was not sent by a human with a username.
+    fn identify_sender(api: &Api, service: &NewTubeService, message: &Message) -> Result<Option<User>, NewTubeServiceError> {
+        let Some(ref from) = message.from else {
+            return Ok(None);
+        };
+
+        let Some(ref username) = from.username else {
+            return Ok(None);
+        };
+
+        if from.is_bot {
+            return Ok(None);
         }
 
-        if let Some(ref text) = message.text {
-            match text.as_str() {
-                // simply check if the bot is still running
-                "/health" => {
-                    Self::send_message(api, chat_id, "I am alive")
+        let is_admin = username == &get_allowed_bot_user();
+        // commands in group chats must not move the notifications of the user to the group
+        let is_start = message.text
+            .as_deref()
+            .and_then(|text| text.split_whitespace().next())
+            .and_then(|command| command.split('@').next())
+            == Some("/start");
+        let user = match service.get_user(from.id as i64)? {
+            Some(user) => User {
+                username: username.clone(),
+                chat_id: if is_start { message.chat.id } else { user.chat_id },
+                approved: user.approved || is_admin,
+                admin: user.admin || is_admin,
+                ..user
+            },
+            None => {
+                let user = User {
+                    id: from.id as i64,
+                    username: username.clone(),
+                    chat_id: message.chat.id,
+                    approved: is_admin,
+                    admin: is_admin,
+                    links: None,
+                };
+
+                if !is_admin {
+                    Self::send_to_admins(api, service, &format!("User {username} requests access. Send /approve {username} to allow it."))?;
                 }
-                _ => ()
+
+                user
             }
+        };
+
+        service.save_user(&user)?;
+        Ok(Some(user))
+    }
+
+    /// Admins see every playlist, all other users only their subscriptions
+    fn visible_playlist_ids(service: &NewTubeService, user: &User) -> Result<Vec<String>, NewTubeServiceError> {
+        if user.admin {
+            service.get_playlist_ids()
+        } else {
+            service.get_subscriptions(user.id)
         }
     }
 
-    /// The sender must be the allowed bot user and the sender must be a human
-    fn sender_is_valid(message: &Message) -> bool {
-        let allowed_user = get_allowed_bot_user();
+    /// Set which kinds of videos of the playlist the user gets. This does not change what other users get
+    fn set_kind_notification(service: &NewTubeService, user: &User, input: &str, kind: &str, notify: &str) -> Result<String, NewTubeServiceError> {
+        let kind = match kind.parse::<VideoKind>() {
+            Ok(kind) => kind,
+            Err(message) => return Ok(message)
+        };
 
-        match message.from {
-            Some(ref from) => match from.username {
-                Some(ref username) => username == &allowed_user && from.is_bot == false,
-                None => false
-            },
-            None => false
+        let notify = match notify {
+            "on" => true,
+            "off" => false,
+            _ => return Ok("Expected on or off".to_string())
+        };
+
+        service.set_kind_notification(Some(user.id), input, kind, notify)?;
+        Ok(format!("Notifications for {kind} videos of {input} are {}", if notify { "on" } else { "off" }))
+    }
+
+    fn set_link_styles(service: &NewTubeService, user: &User, styles: &str) -> Result<String, NewTubeServiceError> {
+        let links = match parse_link_styles(styles) {
+            Ok(links) => links,
+            Err(message) => return Ok(message)
+        };
+
+        service.save_user(&User { links: Some(links.clone()), ..user.clone() })?;
+        Ok(format!("Your links: {}", format_link_styles(&links)))
+    }
+
+    fn approve(api: &Api, service: &NewTubeService, username: &str) -> Result<String, NewTubeServiceError> {
+        match service.get_user_by_name(username)? {
+            Some(user) => {
+                service.save_user(&User { approved: true, ..user.clone() })?;
+                Self::send_message(api, user.chat_id, "An admin allowed you to use the bot. Send /help to see all commands.");
+                Ok(format!("Approved user {username}"))
+            }
+            None => Ok(format!("Unknown user {username}. The user must send a message to the bot first."))
+        }
+    }
+
+    fn revoke(service: &NewTubeService, username: &str) -> Result<String, NewTubeServiceError> {
+        match service.get_user_by_name(username)? {
+            Some(user) if user.admin => Ok("Admins cannot be revoked".to_string()),
+            Some(user) => service.delete_user(user.id).map(|_| format!("Revoked user {username}")),
+            None => Ok(format!("Unknown user {username}"))
+        }
+    }
+
+    fn send_to_admins(api: &Api, service: &NewTubeService, message: &str) -> Result<(), NewTubeServiceError> {
+        service
+            .get_users()?
+            .into_iter()
+            .filter(|user| user.admin)
+            .for_each(|admin| Self::send_message(api, admin.chat_id, message));
+        Ok(())
+    }
+
+    /// A list of all available commands
+    fn usage() -> String {
+        let commands = COMMANDS
+            .iter()
+            .map(|(command, description)| format!("/{command} {description}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!("Available commands:\n{commands}")
+    }
+
+    /// Send a message which might be longer than telegram allows
+    fn send_long_message(api: &Api, chat_id: i64, text: &str) {
+        if let Err(err) = TelegramNotifier::new(api.clone(), chat_id).send_text(text) {
+            println!("failed to send message due to error: {}", err)
         }
     }
 
@@ -113,19 +429,48 @@ impl Bot {
         }
     }
 
-    fn item_to_telegram_message(item: PlaylistItem) -> String {
-        format!(
-            "{}\n{}\n{}\n{}",
-            item.uploader,
-            item.title,
-            item.formatted_duration(),
-            item.link()
-        )
+    fn filters_to_telegram_message(filters: Vec<Filter>) -> String {
+        if filters.is_empty() {
+            return "No filters".to_string();
+        }
+
+        filters
+            .into_iter()
+            .map(|filter| format!("{}: {} {}", filter.id, filter.playlist_id, filter.rule))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn users_to_telegram_message(users: Vec<User>) -> String {
+        users
+            .into_iter()
+            .map(|user| format!(
+                "{}{}",
+                user.username,
+                if user.admin { " (admin)" } else if user.approved { "" } else { " (waiting for approval)" }
+            ))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Create one message from multiple items, separated by empty lines
+    fn items_to_telegram_message(items: Vec<PlaylistItem>, links: &[LinkStyle]) -> String {
+        if items.is_empty() {
+            return "No videos".to_string();
+        }
+
+        items
+            .into_iter()
+            .map(|item| video_message(&item, links))
+            .collect::<Vec<_>>()
+            .join("\n\n")
     }
 }
 
 #[error]
 pub enum BotError {
     #[error(message = "{_0}", impl_from)]
-    NewTubeService(NewTubeServiceError)
-}
\ No newline at end of file
+    NewTubeService(NewTubeServiceError),
+    #[error(message = "{_0}", impl_from)]
+    Outputs(OutputsError),
+}
@@ -0,0 +1,18 @@
+use crate::link::LinkStyle;
+
+/// A telegram user of the bot
+#[derive(Clone, Debug)]
+pub struct User {
+    /// The telegram id of the user
+    pub id: i64,
+    /// The telegram username
+    pub username: String,
+    /// The chat the bot uses to talk to the user
+    pub chat_id: i64,
+    /// If an admin allowed the user to use the bot
+    pub approved: bool,
+    /// If the user can approve and revoke other users
+    pub admin: bool,
+    /// Where the links to videos point to. Uses the styles from the config if not set
+    pub links: Option<Vec<LinkStyle>>,
+}
@@ -0,0 +1,13 @@
+-- A database as it was created and filled by new_tube 0.6.0, before migrations existed
+CREATE TABLE IF NOT EXISTS PlaylistItems (
+    playlist_id TEXT PRIMARY KEY,
+    video_id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    duration REAL NOT NULL,
+    uploader TEXT NOT NULL,
+    previous_video_id NULL
+);
+INSERT OR REPLACE INTO PlaylistItems (playlist_id, video_id, title, duration, uploader, previous_video_id)
+VALUES ('UUaaaaaaaaaaaaaaaaaaaaaa', 'a5xvRqg9yDc', 'Building a workbench, part 3', '1312', 'Wood Works', 'b4PLqW0XkTs');
+INSERT OR REPLACE INTO PlaylistItems (playlist_id, video_id, title, duration, uploader, previous_video_id)
+VALUES ('UUbbbbbbbbbbbbbbbbbbbbbb', 'f9Wq2eRtYu0', 'Friday stream', '0', 'Late Night Radio', NULL);